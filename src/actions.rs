@@ -1,7 +1,9 @@
 use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
 use bevy_enhanced_input::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
 
-use crate::{paste, Delete, Duplicate, Prefab, Save, Selected, SpawnPrefab};
+use crate::{open_import, paste, Cancel, Delete, Duplicate, Prefab, Save, Selected, SpawnPrefab};
 
 pub struct AppActionPlugin;
 
@@ -14,9 +16,11 @@ impl Plugin for AppActionPlugin {
             .add_observer(binding)
             .add_observer(duplicate_selected)
             .add_observer(delete_selected)
+            .add_observer(cancel_selected)
             .add_observer(save)
             .add_observer(exit)
             .add_observer(paste)
+            .add_observer(open_import)
             .add_observer(spawn)
             //camera
             .add_observer(apply_movement)
@@ -57,6 +61,12 @@ fn binding(trigger: Trigger<Binding<AppActions>>, mut players: Query<&mut Action
         .to(KeyCode::Delete)
         .with_conditions(JustPress::default());
 
+    // Cancel generation
+    actions
+        .bind::<CancelAction>()
+        .to(KeyCode::Backspace.with_mod_keys(ModKeys::CONTROL))
+        .with_conditions(JustPress::default());
+
     // Exit
     actions
         .bind::<ExitAction>()
@@ -69,6 +79,12 @@ fn binding(trigger: Trigger<Binding<AppActions>>, mut players: Query<&mut Action
         .to(KeyCode::KeyV.with_mod_keys(ModKeys::CONTROL))
         .with_conditions(JustPress::default());
 
+    // Import
+    actions
+        .bind::<ImportAction>()
+        .to(KeyCode::KeyO.with_mod_keys(ModKeys::CONTROL))
+        .with_conditions(JustPress::default());
+
     // Movement
     actions.bind::<EnableSprint>().to(KeyCode::ShiftLeft);
 
@@ -183,6 +199,26 @@ fn delete_selected(
     }
 }
 
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct CancelAction;
+
+fn cancel_selected(
+    _trigger: Trigger<Fired<CancelAction>>,
+    selected: Query<Entity, (With<Selected>, With<Prefab>)>,
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    mut commands: Commands,
+) {
+    // ctrl+backspace deletes a word in a text field
+    if egui_context.get_mut().wants_keyboard_input() {
+        return;
+    }
+    info!("cancel selected");
+    for e in selected.iter() {
+        commands.trigger_targets(Cancel, e);
+    }
+}
+
 #[derive(Debug, InputAction)]
 #[input_action(output = bool)]
 pub struct SpawnAction;
@@ -214,6 +250,10 @@ fn exit(_trigger: Trigger<Fired<ExitAction>>, mut commands: Commands) {
 #[input_action(output = bool)]
 pub struct PasteAction;
 
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct ImportAction;
+
 #[derive(Debug, InputAction)]
 #[input_action(output = Vec2)]
 struct Move;
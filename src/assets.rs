@@ -1,22 +1,84 @@
+use std::path::Path;
+
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::Prefab;
+use crate::{default_max_in_flight, ComfyClient, Prefab};
+
+/// bump when the saved format changes and handle it in [`RefConfig::migrate`]
+pub const CONFIG_VERSION: u32 = 1;
 
 /// only used for init load, not updated currently
 #[derive(Resource, Serialize, Deserialize, Reflect)]
 #[reflect(Resource)]
 pub struct RefConfig {
+    /// missing in configs saved before versioning
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub comfy: ComfyClient,
+    /// generations sent to ComfyUI at once
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
     pub prefabs: Vec<PrefabConfig>,
 }
 
 impl Default for RefConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
+            comfy: ComfyClient::default(),
+            max_in_flight: default_max_in_flight(),
             prefabs: Vec::new(),
         }
     }
 }
+impl RefConfig {
+    /// loads and migrates the config, a config that fails to parse or is from a newer
+    /// version is kept as `.bak` so the next save doesnt lose it
+    pub fn load(path: &Path) -> Self {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!("Failed to load config file: {:?} {}", path, err);
+                return RefConfig::default();
+            }
+        };
+        match RefConfig::from_bytes(&bytes) {
+            Ok(config) => config,
+            Err(err) => {
+                let backup = path.with_extension("ron.bak");
+                error!("Failed to load {:?}, backed up to {:?}: {}", path, backup, err);
+                std::fs::copy(path, &backup).unwrap_or_default();
+                RefConfig::default()
+            }
+        }
+    }
+
+    /// parses a saved config and brings it up to [`CONFIG_VERSION`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut config = ron::de::from_bytes::<RefConfig>(bytes).map_err(|e| e.to_string())?;
+        config.migrate()?;
+        Ok(config)
+    }
+
+    fn migrate(&mut self) -> Result<(), String> {
+        // saved by a newer build, its fields could be dropped or misread
+        if self.version > CONFIG_VERSION {
+            return Err(format!(
+                "config version {} is newer than {}",
+                self.version, CONFIG_VERSION
+            ));
+        }
+        if self.version < 1 {
+            // seeds were u32, they read as u64 unchanged and are saved as u64 from now on
+            info!("Migrating config from version {} to 1, seeds are now 64 bit", self.version);
+        }
+        self.version = CONFIG_VERSION;
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Reflect)]
 pub struct PrefabConfig {
     pub translation: Vec3,
@@ -24,3 +86,48 @@ pub struct PrefabConfig {
     pub scale: f32,
     pub prefab: Prefab,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Workflow;
+
+    #[test]
+    fn loads_version_0() {
+        // saved before versioning, with u32 seeds and no comfy settings
+        let config = RefConfig::from_bytes(
+            br#"(
+                prefabs: [
+                    (
+                        translation: (1.0, 2.0, 0.0),
+                        rotation: (0.0, 0.0, 0.0, 1.0),
+                        scale: 1.0,
+                        prefab: (
+                            name: "car",
+                            workflow: TextToImage(
+                                seed: 4294967295,
+                                seed_random: false,
+                                prompt: "a red car",
+                                image: Some("ref/car.png"),
+                            ),
+                        ),
+                    ),
+                ],
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.max_in_flight, default_max_in_flight());
+        assert_eq!(config.prefabs.len(), 1);
+        let prefab = &config.prefabs[0].prefab;
+        assert_eq!(prefab.name, "car");
+        assert_eq!(prefab.workflow.seed(), Some((u32::MAX as u64, false)));
+        assert!(matches!(prefab.workflow, Workflow::TextToImage { .. }));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let result = RefConfig::from_bytes(b"(version: 99, prefabs: [])");
+        assert!(result.is_err_and(|err| err.contains("99")));
+    }
+}
@@ -1,280 +1,922 @@
-use bevy::tasks::futures_lite::StreamExt;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use strum::EnumIter;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tokio_util::codec::{BytesCodec, FramedRead};
 use uuid::Uuid;
 
+use crate::{OutputKind, PromptGuard, PromptRouter};
+
 use reqwest::{multipart, Client};
 
-// Change the server address if needed.
-pub const SERVER_ADDRESS: &str = "127.0.0.1:8188";
+pub type ComfySocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// how long a prompt can go without a message before we ask the server about it
+const PROMPT_IDLE: Duration = Duration::from_secs(30);
+
+#[derive(EnumIter, PartialEq, Eq, Reflect, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub enum ComfyScheme {
+    #[default]
+    Http,
+    Https,
+}
+
+impl ComfyScheme {
+    pub fn http(&self) -> &'static str {
+        match self {
+            ComfyScheme::Http => "http",
+            ComfyScheme::Https => "https",
+        }
+    }
+
+    pub fn ws(&self) -> &'static str {
+        match self {
+            ComfyScheme::Http => "ws",
+            ComfyScheme::Https => "wss",
+        }
+    }
+}
 
-pub async fn connect_comfy(
-) -> Result<(Client, String, WebSocketStream<MaybeTlsStream<TcpStream>>), Box<dyn Error>> {
-    let client = Client::new();
-    let client_id = Uuid::new_v4().to_string();
-    let ws_url = format!("ws://{}/ws?clientId={}", SERVER_ADDRESS, client_id);
-    let (ws, _response) = connect_async(ws_url).await?;
-    Ok((client, client_id, ws))
+impl Display for ComfyScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComfyScheme::Http => write!(f, "http / ws"),
+            ComfyScheme::Https => write!(f, "https / wss"),
+        }
+    }
 }
 
-/// Sends the prompt to the server and returns the JSON response.
-pub async fn queue_prompt(
-    client: &Client,
-    prompt: &Value,
-    client_id: &str,
-) -> Result<Value, Box<dyn Error>> {
-    let url = format!("http://{}/prompt", SERVER_ADDRESS);
-    let payload = serde_json::json!({
-        "prompt": prompt,
-        "client_id": client_id,
-    });
-    let resp = client
-        .post(&url)
-        .json(&payload)
-        .send()
-        .await?
-        .json()
-        .await?;
-    Ok(resp)
+/// Everything that can go wrong talking to ComfyUI
+#[derive(Debug, Clone)]
+pub enum ComfyError {
+    /// server not running, wrong host, or timed out
+    Connection(String),
+    /// non success response
+    Http { status: u16, body: String },
+    /// `/prompt` rejected the workflow
+    Validation {
+        message: String,
+        node_errors: Vec<NodeError>,
+    },
+    /// a node threw while running
+    Execution {
+        node_id: String,
+        node_type: String,
+        message: String,
+        traceback: Vec<String>,
+    },
+    /// prompt was interrupted on the server
+    Interrupted,
+    /// finished but didnt produce what we expected
+    MissingOutput(String),
+    /// workflow template not found in assets/workflows or failed to load
+    MissingTemplate(String),
+    /// io, bad json, websocket protocol errors
+    Other(String),
 }
 
-/// Retrieves an image by constructing a URL with query parameters.
-pub async fn get_image(
-    client: &Client,
-    filename: &str,
-    subfolder: &str,
-    folder_type: &str,
-) -> Result<Vec<u8>, Box<dyn Error>> {
-    let url = format!("http://{}/view", SERVER_ADDRESS);
-    let params = [
-        ("filename", filename),
-        ("subfolder", subfolder),
-        ("type", folder_type),
-    ];
-    let bytes = client
-        .get(&url)
-        .query(&params)
-        .send()
-        .await?
-        .bytes()
-        .await?;
-    Ok(bytes.to_vec())
+#[derive(Debug, Clone)]
+pub struct NodeError {
+    pub node_id: String,
+    pub class_type: String,
+    pub errors: Vec<String>,
 }
 
-/// Retrieves the history JSON for a given prompt id.
-pub async fn get_history(client: &Client, prompt_id: &str) -> Result<Value, Box<dyn Error>> {
-    let url = format!("http://{}/history/{}", SERVER_ADDRESS, prompt_id);
-    let resp = client.get(&url).send().await?.json().await?;
-    Ok(resp)
+impl ComfyError {
+    /// turns non success responses into [`ComfyError::Http`]
+    pub async fn check(resp: reqwest::Response) -> Result<reqwest::Response, ComfyError> {
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        let body = resp.text().await.unwrap_or_default();
+        Err(ComfyError::Http {
+            status: status.as_u16(),
+            body,
+        })
+    }
+
+    /// parses the `error` and `node_errors` from a rejected `/prompt` response
+    pub fn from_prompt_response(status: u16, body: &Value) -> Self {
+        let Some(message) = body["error"]["message"].as_str() else {
+            return ComfyError::Http {
+                status,
+                body: body.to_string(),
+            };
+        };
+        let node_errors = body["node_errors"]
+            .as_object()
+            .map(|nodes| {
+                nodes
+                    .iter()
+                    .map(|(node_id, node)| NodeError {
+                        node_id: node_id.clone(),
+                        class_type: node["class_type"].as_str().unwrap_or_default().to_string(),
+                        errors: node["errors"]
+                            .as_array()
+                            .map(|errors| {
+                                errors
+                                    .iter()
+                                    .map(|e| {
+                                        format!(
+                                            "{}: {}",
+                                            e["message"].as_str().unwrap_or_default(),
+                                            e["details"].as_str().unwrap_or_default()
+                                        )
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        ComfyError::Validation {
+            message: message.to_string(),
+            node_errors,
+        }
+    }
+
+    /// error or interrupt messages from the websocket
+    pub fn from_message(message: &Value) -> Option<Self> {
+        let data = &message["data"];
+        let text = |key: &str| data[key].as_str().unwrap_or_default().to_string();
+        match message["type"].as_str()? {
+            "execution_error" => Some(ComfyError::Execution {
+                node_id: text("node_id"),
+                node_type: text("node_type"),
+                message: format!("{}: {}", text("exception_type"), text("exception_message")),
+                traceback: data["traceback"]
+                    .as_array()
+                    .map(|t| {
+                        t.iter()
+                            .filter_map(|l| l.as_str().map(|s| s.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }),
+            "execution_interrupted" => Some(ComfyError::Interrupted),
+            "error" => Some(ComfyError::Other(data.to_string())),
+            _ => None,
+        }
+    }
 }
 
-/// Listens on the websocket until the prompt execution is done,
-/// then downloads images from the history.
-pub async fn get_images(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-    client: &Client,
-    prompt: &Value,
-    client_id: &str,
-    //ctx: &mut TaskContext,
-) -> Result<HashMap<String, Vec<Vec<u8>>>, Box<dyn Error>> {
-    // Submit the prompt and get the prompt_id.
-    let queue_resp = queue_prompt(client, prompt, client_id).await?;
-    // dbg!(&queue_resp);
-    let prompt_id = queue_resp["prompt_id"]
-        .as_str()
-        .ok_or("prompt_id not found")?;
-
-    // // Listen for websocket messages until we see one indicating execution is done.
-
-    while let Some(msg) = ws.next().await {
-        let msg = msg?;
-        if msg.is_text() {
-            let text = msg.into_text()?;
-            //dbg!(&text);
-            let message: Value = serde_json::from_str(&text)?;
-            if message["type"] == "executing" {
-                let data = &message["data"];
-                // When data["node"] is null and the prompt_id matches, execution is done.
-                if data["node"].is_null() && data["prompt_id"] == prompt_id {
-                    break;
+impl Display for ComfyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComfyError::Connection(msg) => write!(f, "Connection failed: {}", msg),
+            ComfyError::Http { status, body } => write!(f, "HTTP {}: {}", status, body),
+            ComfyError::Validation {
+                message,
+                node_errors,
+            } => {
+                write!(f, "{}", message)?;
+                for node in node_errors {
+                    for error in &node.errors {
+                        write!(f, "\n  {} ({}): {}", node.class_type, node.node_id, error)?;
+                    }
                 }
+                Ok(())
             }
-            if message["type"] == "progress" {
-                // let data = &message["data"];
-                // let node = data["node"].as_str().unwrap();
-                // let max = data["max"].as_i64().unwrap();
-                // let value = data["value"].as_i64().unwrap();
-                // let progress = (value as f64 / max as f64) * 100.0;
-
-                // TODO: synchronize with main thread to see progress, println works, info spans dont,
-                // https://github.com/EkardNT/bevy-tokio-tasks#how-to-synchronize-with-the-main-thread
-                //println!("Progress: {} - {:4.0}", node, progress);
-                // ctx.run_on_main_thread(move |ctx| {
-                //     // The inner context gives access to a mutable Bevy World reference.
-                //     let _world: &mut World = ctx.world;
-                // }).await;
+            ComfyError::Execution {
+                node_id,
+                node_type,
+                message,
+                traceback,
+            } => {
+                write!(f, "{} ({}) failed: {}", node_type, node_id, message)?;
+                for line in traceback {
+                    write!(f, "\n{}", line.trim_end())?;
+                }
+                Ok(())
             }
-            if message["type"] == "error" {
-                let data = &message["data"];
-                dbg!("Error: {:?}", data);
+            ComfyError::Interrupted => write!(f, "Interrupted"),
+            ComfyError::MissingOutput(what) => write!(f, "Missing output: {}", what),
+            ComfyError::MissingTemplate(name) => {
+                write!(f, "Workflow template '{}' not loaded", name)
             }
+            ComfyError::Other(msg) => write!(f, "{}", msg),
         }
     }
+}
 
-    // Get history for the executed prompt.
-    let history: Value = get_history(client, prompt_id).await?;
-    //dbg!("History: {:?}", &history);
-    let history_for_prompt = &history[prompt_id];
-    let outputs = history_for_prompt["outputs"]
-        .as_object()
-        .ok_or("outputs not found")?;
+impl std::error::Error for ComfyError {}
 
-    let mut output_images: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+impl From<reqwest::Error> for ComfyError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() {
+            ComfyError::Connection(e.to_string())
+        } else if let Some(status) = e.status() {
+            ComfyError::Http {
+                status: status.as_u16(),
+                body: e.to_string(),
+            }
+        } else {
+            ComfyError::Other(e.to_string())
+        }
+    }
+}
 
-    // Iterate through each node's output.
-    for (node_id, node_output) in outputs.iter() {
-        let mut images_output = Vec::new();
-        if let Some(output) = node_output.get("images") {
-            if let Some(arr) = output.as_array() {
-                for image in arr {
-                    let filename = image["filename"].as_str().ok_or("filename not found")?;
-                    let subfolder = image["subfolder"].as_str().ok_or("subfolder not found")?;
-                    let folder_type = image["type"].as_str().ok_or("type not found")?;
-                    let image_data = get_image(client, filename, subfolder, folder_type).await?;
-                    images_output.push(image_data);
-                }
+impl From<tokio_tungstenite::tungstenite::Error> for ComfyError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        use tokio_tungstenite::tungstenite::Error as WsError;
+        match e {
+            WsError::Io(_) | WsError::ConnectionClosed | WsError::AlreadyClosed => {
+                ComfyError::Connection(e.to_string())
             }
+            _ => ComfyError::Other(e.to_string()),
         }
-        output_images.insert(node_id.clone(), images_output);
     }
+}
+
+impl From<std::io::Error> for ComfyError {
+    fn from(e: std::io::Error) -> Self {
+        ComfyError::Other(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ComfyError {
+    fn from(e: serde_json::Error) -> Self {
+        ComfyError::Other(e.to_string())
+    }
+}
 
-    Ok(output_images)
+/// Progress messages from the ComfyUI websocket for a single prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComfyProgress {
+    /// prompt accepted by the server
+    Queued { prompt_id: String },
+    /// node started executing, title from the prompt `_meta` if it has one
+    Executing { node: String, title: Option<String> },
+    /// nodes skipped because their outputs were cached
+    Cached(Vec<String>),
+    /// sampler style step progress within a node
+    Step { node: String, value: u32, max: u32 },
 }
 
-/// Listens on the websocket until the prompt execution is done,
-/// then downloads images from the history.
-pub async fn get_models(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-    client: &Client,
-    prompt: &Value,
-    client_id: &str,
-    model_node_id: &str,
-    //ctx: &mut TaskContext,
-) -> Result<HashMap<String, Vec<Vec<u8>>>, Box<dyn Error>> {
-    // Submit the prompt and get the prompt_id.
-    let queue_resp = queue_prompt(client, prompt, client_id).await?;
-    // dbg!(&queue_resp);
-    let prompt_id = queue_resp["prompt_id"]
-        .as_str()
-        .ok_or("prompt_id not found")?;
-
-    // // Listen for websocket messages until we see one indicating execution is done.
-
-    while let Some(msg) = ws.next().await {
-        let msg = msg?;
-        if msg.is_text() {
-            let text = msg.into_text()?;
-            //dbg!(&text);
-            let message: Value = serde_json::from_str(&text)?;
-            if message["type"] == "executing" {
-                let data = &message["data"];
-                // When data["node"] is null and the prompt_id matches, execution is done.
-                if data["node"].is_null() && data["prompt_id"] == prompt_id {
-                    break;
+impl ComfyProgress {
+    /// parses a websocket message, prompt is used to look up node titles
+    pub fn from_message(message: &Value, prompt: &Value) -> Option<Self> {
+        let data = &message["data"];
+        match message["type"].as_str()? {
+            "executing" => {
+                let node = data["node"].as_str()?.to_string();
+                let title = prompt[&node]["_meta"]["title"]
+                    .as_str()
+                    .map(|s| s.to_string());
+                Some(ComfyProgress::Executing { node, title })
+            }
+            "execution_cached" => Some(ComfyProgress::Cached(
+                data["nodes"]
+                    .as_array()?
+                    .iter()
+                    .filter_map(|n| n.as_str().map(|s| s.to_string()))
+                    .collect(),
+            )),
+            "progress" => Some(ComfyProgress::Step {
+                node: data["node"].as_str()?.to_string(),
+                value: data["value"].as_u64()? as u32,
+                max: data["max"].as_u64()? as u32,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Where ComfyUI lives and how to talk to it, cloned into each background task
+#[derive(Resource, Clone, Debug, Reflect, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct ComfyClient {
+    /// host and port, no scheme, ex "127.0.0.1:8188"
+    pub host: String,
+    pub scheme: ComfyScheme,
+    /// seconds
+    pub connect_timeout: f32,
+    /// seconds, model downloads can be large
+    pub request_timeout: f32,
+    /// ComfyUI's input folder as the server sees it, relative to where it was started
+    /// or absolute, nodes that take a file path instead of a name read uploads from here
+    pub input_dir: String,
+    /// shared by the websocket and every prompt we queue, so messages find their way back
+    #[serde(skip)]
+    pub client_id: String,
+    #[reflect(ignore)]
+    #[serde(skip)]
+    http: Client,
+    #[reflect(ignore)]
+    #[serde(skip)]
+    pub(crate) router: PromptRouter,
+}
+
+impl Default for ComfyClient {
+    fn default() -> Self {
+        let mut client = Self {
+            host: "127.0.0.1:8188".to_string(),
+            scheme: ComfyScheme::Http,
+            connect_timeout: 5.0,
+            request_timeout: 60.0,
+            input_dir: "input".to_string(),
+            client_id: Uuid::new_v4().to_string(),
+            http: Client::new(),
+            router: PromptRouter::default(),
+        };
+        client.rebuild();
+        client
+    }
+}
+
+impl ComfyClient {
+    /// rebuilds the shared http client, call after changing timeouts
+    pub fn rebuild(&mut self) {
+        self.http = Client::builder()
+            .connect_timeout(Duration::from_secs_f32(self.connect_timeout.max(0.1)))
+            .timeout(Duration::from_secs_f32(self.request_timeout.max(0.1)))
+            .build()
+            .unwrap_or_else(|e| {
+                error!("Failed to build http client: {:?}", e);
+                Client::new()
+            });
+    }
+
+    pub fn http(&self) -> &Client {
+        &self.http
+    }
+
+    pub fn connected(&self) -> bool {
+        self.router.connected()
+    }
+
+    pub(crate) fn host(&self) -> &str {
+        self.host.trim().trim_end_matches('/')
+    }
+
+    pub fn http_url(&self, path: &str) -> String {
+        format!("{}://{}/{}", self.scheme.http(), self.host(), path.trim_start_matches('/'))
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("{}://{}/ws?clientId={}", self.scheme.ws(), self.host(), self.client_id)
+    }
+
+    /// server side path of an uploaded file, for nodes that load a path
+    pub fn input_path(&self, filename: &str) -> String {
+        let dir = self.input_dir.trim().trim_end_matches(['/', '\\']);
+        if dir.is_empty() {
+            return filename.to_string();
+        }
+        format!("{}/{}", dir, filename)
+    }
+
+    /// Opens a websocket for our client id
+    pub async fn connect(&self) -> Result<ComfySocket, ComfyError> {
+        let timeout = Duration::from_secs_f32(self.connect_timeout.max(0.1));
+        let (ws, _response) = tokio::time::timeout(timeout, connect_async(self.ws_url()))
+            .await
+            .map_err(|_| ComfyError::Connection(format!("timed out connecting to {}", self.host())))??;
+        Ok(ws)
+    }
+
+    /// Sends the prompt to the server and returns the JSON response.
+    /// Servers that dont take a `prompt_id` make their own, read it from the response.
+    pub async fn queue_prompt(&self, prompt: &Value, prompt_id: &str) -> Result<Value, ComfyError> {
+        let url = self.http_url("prompt");
+        let payload = serde_json::json!({
+            "prompt": prompt,
+            "prompt_id": prompt_id,
+            "client_id": self.client_id,
+        });
+        let resp = self.http.post(&url).json(&payload).send().await?;
+        let status = resp.status();
+        let body: Value = resp.json().await.unwrap_or_default();
+        // validation failures come back as 400 with per node errors
+        if !status.is_success() || body["node_errors"].as_object().is_some_and(|o| !o.is_empty()) {
+            return Err(ComfyError::from_prompt_response(status.as_u16(), &body));
+        }
+        Ok(body)
+    }
+
+    /// Retrieves an image by constructing a URL with query parameters.
+    pub async fn get_image(
+        &self,
+        filename: &str,
+        subfolder: &str,
+        folder_type: &str,
+    ) -> Result<Vec<u8>, ComfyError> {
+        let url = self.http_url("view");
+        let params = [
+            ("filename", filename),
+            ("subfolder", subfolder),
+            ("type", folder_type),
+        ];
+        let resp = self.http.get(&url).query(&params).send().await?;
+        let bytes = ComfyError::check(resp).await?.bytes().await?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Retrieves the history JSON for a given prompt id.
+    pub async fn get_history(&self, prompt_id: &str) -> Result<Value, ComfyError> {
+        let url = self.http_url(&format!("history/{}", prompt_id));
+        let resp = self.http.get(&url).send().await?;
+        Ok(ComfyError::check(resp).await?.json().await?)
+    }
+
+    /// Returns every node class with its inputs and outputs.
+    pub async fn get_object_info(&self) -> Result<Value, ComfyError> {
+        let url = self.http_url("object_info");
+        let resp = self.http.get(&url).send().await?;
+        Ok(ComfyError::check(resp).await?.json().await?)
+    }
+
+    /// Returns the file names in a model folder, ex "checkpoints", "loras".
+    pub async fn list_models(&self, folder: &str) -> Result<Vec<String>, ComfyError> {
+        let url = self.http_url(&format!("models/{}", folder));
+        let resp = self.http.get(&url).send().await?;
+        Ok(ComfyError::check(resp).await?.json().await?)
+    }
+
+    /// Returns the running and pending queue.
+    pub async fn get_queue(&self) -> Result<Value, ComfyError> {
+        let url = self.http_url("queue");
+        let resp = self.http.get(&url).send().await?;
+        Ok(ComfyError::check(resp).await?.json().await?)
+    }
+
+    /// Interrupts the prompt currently executing.
+    pub async fn interrupt(&self, prompt_id: &str) -> Result<(), ComfyError> {
+        let url = self.http_url("interrupt");
+        // older servers ignore the body and interrupt whatever is running
+        let payload = serde_json::json!({ "prompt_id": prompt_id });
+        let resp = self.http.post(&url).json(&payload).send().await?;
+        ComfyError::check(resp).await?;
+        Ok(())
+    }
+
+    /// Removes pending prompts from the queue.
+    pub async fn delete_queued(&self, prompt_ids: &[String]) -> Result<(), ComfyError> {
+        let url = self.http_url("queue");
+        let payload = serde_json::json!({ "delete": prompt_ids });
+        let resp = self.http.post(&url).json(&payload).send().await?;
+        ComfyError::check(resp).await?;
+        Ok(())
+    }
+
+    /// Whether the prompt is running or pending, as (running, pending).
+    pub async fn queue_status(&self, prompt_id: &str) -> Result<(bool, bool), ComfyError> {
+        let queue = self.get_queue().await?;
+        // queue entries are [number, prompt_id, prompt, extra_data, outputs]
+        let contains = |key: &str| {
+            queue[key]
+                .as_array()
+                .is_some_and(|q| q.iter().any(|item| item[1] == prompt_id))
+        };
+        Ok((contains("queue_running"), contains("queue_pending")))
+    }
+
+    /// Interrupts the prompt if it is running, or removes it if still pending.
+    pub async fn cancel_prompt(&self, prompt_id: &str) -> Result<(), ComfyError> {
+        match self.queue_status(prompt_id).await? {
+            (true, _) => self.interrupt(prompt_id).await,
+            (_, true) => self.delete_queued(&[prompt_id.to_string()]).await,
+            // already finished
+            _ => Ok(()),
+        }
+    }
+
+    /// Queues the prompt and waits on the shared connection until its execution is done,
+    /// forwarding progress as it goes. Returns the prompt id.
+    pub async fn run_prompt(
+        &self,
+        prompt: &Value,
+        mut on_progress: impl FnMut(ComfyProgress) + Send,
+    ) -> Result<String, ComfyError> {
+        // the id is ours before the request goes out, so a task aborted while
+        // queueing still cancels the prompt
+        let requested_id = Uuid::new_v4().to_string();
+        let mut guard = PromptGuard::new(self, requested_id.clone());
+        // Submit the prompt and get the prompt_id.
+        let queue_resp = self.queue_prompt(prompt, &requested_id).await?;
+        let prompt_id = queue_resp["prompt_id"]
+            .as_str()
+            .ok_or(ComfyError::MissingOutput("prompt_id".to_string()))?
+            .to_string();
+        guard.set_prompt_id(prompt_id.clone());
+        let mut subscription = self.router.subscribe(&prompt_id);
+        on_progress(ComfyProgress::Queued {
+            prompt_id: prompt_id.clone(),
+        });
+
+        // it could have finished before we subscribed, cached prompts are quick
+        if let Err(err) = self.recover_prompt(&prompt_id).await {
+            warn!("Failed to check history for {}: {}", prompt_id, err);
+        }
+
+        loop {
+            let message = match tokio::time::timeout(PROMPT_IDLE, subscription.recv()).await {
+                Ok(Some(message)) => message,
+                Ok(None) => return Err(ComfyError::Connection("connection closed".to_string())),
+                // quiet for a while, the done message could have been lost to a restart
+                Err(_) => {
+                    if let Err(err) = self.check_prompt(&prompt_id).await {
+                        guard.disarm();
+                        return Err(err);
+                    }
+                    continue;
+                }
+            };
+            let data = &message["data"];
+            match ComfyProgress::from_message(&message, prompt) {
+                // When data["node"] is null, execution is done.
+                None if message["type"] == "executing" && data["node"].is_null() => {
+                    guard.disarm();
+                    return Ok(prompt_id);
                 }
+                Some(progress) => on_progress(progress),
+                None => {}
             }
-            if message["type"] == "progress" {
-                // let data = &message["data"];
-                // let node = data["node"].as_str().unwrap();
-                // let max = data["max"].as_i64().unwrap();
-                // let value = data["value"].as_i64().unwrap();
-                // let progress = (value as f64 / max as f64) * 100.0;
-
-                // TODO: synchronize with main thread to see progress, println works, info spans dont,
-                // https://github.com/EkardNT/bevy-tokio-tasks#how-to-synchronize-with-the-main-thread
-                //println!("Progress: {} - {:4.0}", node, progress);
-                // ctx.run_on_main_thread(move |ctx| {
-                //     // The inner context gives access to a mutable Bevy World reference.
-                //     let _world: &mut World = ctx.world;
-                // }).await;
+            if let Some(err) = ComfyError::from_message(&message) {
+                guard.disarm();
+                return Err(err);
             }
-            if message["type"] == "error" {
-                let data = &message["data"];
-                dbg!("Error: {:?}", data);
+        }
+    }
+
+    /// Replays how the prompt ended if it is in the history, fails if the server
+    /// doesnt know it at all anymore. Request errors are logged, the connection
+    /// task checks again once it is back.
+    async fn check_prompt(&self, prompt_id: &str) -> Result<(), ComfyError> {
+        match self.recover_prompt(prompt_id).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => {
+                warn!("Failed to check history for {}: {}", prompt_id, err);
+                return Ok(());
+            }
+        }
+        match self.queue_status(prompt_id).await {
+            // it could have finished between the two requests
+            Ok((false, false)) if !self.recover_prompt(prompt_id).await.unwrap_or(true) => {
+                Err(ComfyError::Other(format!(
+                    "prompt {} is no longer queued or in the history",
+                    prompt_id
+                )))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                warn!("Failed to check queue for {}: {}", prompt_id, err);
+                Ok(())
             }
         }
     }
 
-    // Get history for the executed prompt.
-    let history: Value = get_history(client, prompt_id).await?;
-    //dbg!("History: {:?}", &history);
-    let history_for_prompt = &history[prompt_id];
-    let outputs = history_for_prompt["outputs"]
-        .as_object()
-        .ok_or("outputs not found")?;
+    /// Runs the prompt, then downloads images from the history.
+    pub async fn get_images(
+        &self,
+        prompt: &Value,
+        on_progress: impl FnMut(ComfyProgress) + Send,
+    ) -> Result<HashMap<String, Vec<Vec<u8>>>, ComfyError> {
+        let prompt_id = self.run_prompt(prompt, on_progress).await?;
+
+        // Get history for the executed prompt.
+        let history: Value = self.get_history(&prompt_id).await?;
+        let history_for_prompt = &history[&prompt_id];
+        let outputs = history_for_prompt["outputs"]
+            .as_object()
+            .ok_or(ComfyError::MissingOutput("outputs".to_string()))?;
+
+        let mut output_images: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+
+        // Iterate through each node's output.
+        for (node_id, node_output) in outputs.iter() {
+            let images_output = self.node_images(node_id, node_output).await?;
+            output_images.insert(node_id.clone(), images_output);
+        }
+
+        Ok(output_images)
+    }
 
-    //dbg!("outputs: {:?}", &outputs);
+    /// Runs the prompt, then downloads the images or models of the given nodes.
+    pub async fn get_outputs(
+        &self,
+        prompt: &Value,
+        nodes: &[(&str, OutputKind)],
+        on_progress: impl FnMut(ComfyProgress) + Send,
+    ) -> Result<HashMap<String, Vec<Vec<u8>>>, ComfyError> {
+        let prompt_id = self.run_prompt(prompt, on_progress).await?;
 
-    let mut output_models: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        let history: Value = self.get_history(&prompt_id).await?;
+        let outputs = history[&prompt_id]["outputs"]
+            .as_object()
+            .ok_or(ComfyError::MissingOutput("outputs".to_string()))?;
 
-    // Iterate through each node's output.
-    for (node_id, node_output) in outputs.iter() {
-        if node_id != model_node_id {
-            continue;
+        let mut files = HashMap::new();
+        for (node_id, kind) in nodes {
+            let Some(node_output) = outputs.get(*node_id) else {
+                continue;
+            };
+            let data = match kind {
+                OutputKind::Image => self.node_images(node_id, node_output).await?,
+                OutputKind::Model => self.node_models(node_id, node_output).await?,
+            };
+            files.insert(node_id.to_string(), data);
         }
+        Ok(files)
+    }
+
+    async fn node_images(
+        &self,
+        node_id: &str,
+        node_output: &Value,
+    ) -> Result<Vec<Vec<u8>>, ComfyError> {
         let mut images_output = Vec::new();
-        if let Some(output) = node_output.get("model_file") {
-            if let Some(arr) = output.as_array() {
-                for file in arr {
-                    let filename = file.as_str().ok_or("filename not found")?;
-                    // dbg!("filename: {:?}", filename);
-                    let model_data = get_image(client, filename, "", "output").await?;
-                    // dbg!("model_data: {:?}", &model_data.len());
-                    images_output.push(model_data);
-                }
+        if let Some(arr) = node_output.get("images").and_then(|o| o.as_array()) {
+            for image in arr {
+                let (Some(filename), Some(subfolder), Some(folder_type)) = (
+                    image["filename"].as_str(),
+                    image["subfolder"].as_str(),
+                    image["type"].as_str(),
+                ) else {
+                    return Err(ComfyError::MissingOutput(format!(
+                        "image file for node {}",
+                        node_id
+                    )));
+                };
+                let image_data = self.get_image(filename, subfolder, folder_type).await?;
+                images_output.push(image_data);
             }
         }
-        output_models.insert(node_id.clone(), images_output);
+        Ok(images_output)
     }
 
-    Ok(output_models)
-}
+    async fn node_models(
+        &self,
+        node_id: &str,
+        node_output: &Value,
+    ) -> Result<Vec<Vec<u8>>, ComfyError> {
+        let mut models_output = Vec::new();
+        if let Some(arr) = node_output.get("model_file").and_then(|o| o.as_array()) {
+            for file in arr {
+                let filename = file.as_str().ok_or(ComfyError::MissingOutput(format!(
+                    "model file for node {}",
+                    node_id
+                )))?;
+                let model_data = self.get_image(filename, "", "output").await?;
+                models_output.push(model_data);
+            }
+        }
+        Ok(models_output)
+    }
+
+    /// Runs the prompt, then downloads the models from the history.
+    pub async fn get_models(
+        &self,
+        prompt: &Value,
+        model_node_id: &str,
+        on_progress: impl FnMut(ComfyProgress) + Send,
+    ) -> Result<HashMap<String, Vec<Vec<u8>>>, ComfyError> {
+        let prompt_id = self.run_prompt(prompt, on_progress).await?;
+
+        // Get history for the executed prompt.
+        let history: Value = self.get_history(&prompt_id).await?;
+        let history_for_prompt = &history[&prompt_id];
+        let outputs = history_for_prompt["outputs"]
+            .as_object()
+            .ok_or(ComfyError::MissingOutput("outputs".to_string()))?;
+
+        let mut output_models: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+
+        // Iterate through each node's output.
+        for (node_id, node_output) in outputs.iter() {
+            if node_id != model_node_id {
+                continue;
+            }
+            let models_output = self.node_models(node_id, node_output).await?;
+            output_models.insert(node_id.clone(), models_output);
+        }
+
+        Ok(output_models)
+    }
 
-pub async fn upload_image(
-    client: &Client,
-    file_path: String,
-    filename: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let url = format!("http://{}/upload/image", SERVER_ADDRESS);
+    pub async fn upload_image(
+        &self,
+        file_path: String,
+        filename: String,
+    ) -> Result<(), ComfyError> {
+        self.upload_file(file_path, filename, "image/png").await
+    }
+
+    /// uploads a glb to the input folder through the image upload, ComfyUI has no mesh
+    /// upload but writes whatever it gets byte for byte, with overwrite set it never
+    /// opens the file as an image
+    pub async fn upload_mesh(&self, file_path: String, filename: String) -> Result<(), ComfyError> {
+        self.upload_file(file_path, filename, "model/gltf-binary")
+            .await
+    }
+
+    async fn upload_file(
+        &self,
+        file_path: String,
+        filename: String,
+        mime: &str,
+    ) -> Result<(), ComfyError> {
+        let url = self.http_url("upload/image");
+
+        // let mut headers = HeaderMap::new();
+        // headers.insert(USER_AGENT, HeaderValue::from_static("reqwest"));
+        // headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
 
-    // let mut headers = HeaderMap::new();
-    // headers.insert(USER_AGENT, HeaderValue::from_static("reqwest"));
-    // headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        // Open the file asynchronously.
+        let file = tokio::fs::File::open(&file_path).await?;
+        // Wrap the file in a stream of bytes.
+        let file_stream = FramedRead::new(file, BytesCodec::new());
 
-    // Open the file asynchronously.
-    let file = tokio::fs::File::open(&file_path).await?;
-    // Wrap the file in a stream of bytes.
-    let file_stream = FramedRead::new(file, BytesCodec::new());
+        // Build the multipart part using the stream.
+        let file_part = multipart::Part::stream(reqwest::Body::wrap_stream(file_stream))
+            .file_name(filename.clone())
+            .mime_str(mime)?;
+
+        // Build the multipart form with additional fields.
+        let form = multipart::Form::new()
+            .part("image", file_part)
+            .text("type", "input".to_string())
+            .text("subfolder", "".to_string())
+            .text("overwrite", "1".to_string());
+
+        let resp = self.http.post(url).multipart(form).send().await?;
+        ComfyError::check(resp).await?;
+
+        Ok(())
+    }
 
-    // Build the multipart part using the stream.
-    let file_part = multipart::Part::stream(reqwest::Body::wrap_stream(file_stream))
-        .file_name(filename.clone())
-        .mime_str("image/png")?;
+    /// uploads a png mask, ComfyUI copies `original` from its input folder with the
+    /// alpha replaced by the mask's and saves it as `filename`
+    pub async fn upload_mask(
+        &self,
+        mask: Vec<u8>,
+        filename: String,
+        original: String,
+    ) -> Result<(), ComfyError> {
+        let url = self.http_url("upload/mask");
 
-    // Build the multipart form with additional fields.
-    let form = multipart::Form::new()
-        .part("image", file_part)
-        .text("type", "input".to_string())
-        .text("subfolder", "".to_string())
-        .text("overwrite", "1".to_string());
+        let mask_part = multipart::Part::bytes(mask)
+            .file_name(filename)
+            .mime_str("image/png")?;
+        let original_ref = serde_json::json!({
+            "filename": original,
+            "subfolder": "",
+            "type": "input",
+        });
 
-    let _resp = client.post(url).multipart(form).send().await?;
+        let form = multipart::Form::new()
+            .part("image", mask_part)
+            .text("original_ref", original_ref.to_string())
+            .text("type", "input".to_string())
+            .text("subfolder", "".to_string())
+            .text("overwrite", "1".to_string());
 
-    //dbg!(&resp);
+        let resp = self.http.post(url).multipart(form).send().await?;
+        ComfyError::check(resp).await?;
 
-    Ok(())
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn prompt_response_node_errors() {
+        // what /prompt answers a workflow with a missing checkpoint
+        let body = json!({
+            "error": {
+                "type": "prompt_outputs_failed_validation",
+                "message": "Prompt outputs failed validation",
+                "details": "",
+                "extra_info": {}
+            },
+            "node_errors": {
+                "4": {
+                    "errors": [{
+                        "type": "value_not_in_list",
+                        "message": "Value not in list",
+                        "details": "ckpt_name: 'missing.safetensors' not in []",
+                        "extra_info": {}
+                    }],
+                    "dependent_outputs": ["9"],
+                    "class_type": "CheckpointLoaderSimple"
+                }
+            }
+        });
+        let err = ComfyError::from_prompt_response(400, &body);
+        let ComfyError::Validation {
+            message,
+            node_errors,
+        } = &err
+        else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        assert_eq!(message, "Prompt outputs failed validation");
+        assert_eq!(node_errors.len(), 1);
+        assert_eq!(node_errors[0].node_id, "4");
+        assert_eq!(node_errors[0].class_type, "CheckpointLoaderSimple");
+        assert_eq!(
+            node_errors[0].errors,
+            ["Value not in list: ckpt_name: 'missing.safetensors' not in []"]
+        );
+        let text = err.to_string();
+        assert!(text.contains("CheckpointLoaderSimple (4): Value not in list"));
+    }
+
+    #[test]
+    fn prompt_response_without_error_is_http() {
+        let err = ComfyError::from_prompt_response(500, &json!({ "oops": true }));
+        assert!(matches!(err, ComfyError::Http { status: 500, .. }));
+    }
+
+    #[test]
+    fn websocket_errors() {
+        let message = json!({
+            "type": "execution_error",
+            "data": {
+                "prompt_id": "p",
+                "node_id": "3",
+                "node_type": "KSampler",
+                "exception_type": "RuntimeError",
+                "exception_message": "out of memory",
+                "traceback": ["line 1\n", "line 2\n"]
+            }
+        });
+        let Some(ComfyError::Execution {
+            node_id,
+            node_type,
+            message,
+            traceback,
+        }) = ComfyError::from_message(&message)
+        else {
+            panic!("expected an execution error");
+        };
+        assert_eq!(node_id, "3");
+        assert_eq!(node_type, "KSampler");
+        assert_eq!(message, "RuntimeError: out of memory");
+        assert_eq!(traceback.len(), 2);
+
+        let interrupted = json!({ "type": "execution_interrupted", "data": {} });
+        assert!(matches!(
+            ComfyError::from_message(&interrupted),
+            Some(ComfyError::Interrupted)
+        ));
+        let progress = json!({ "type": "progress", "data": { "value": 1, "max": 2 } });
+        assert!(ComfyError::from_message(&progress).is_none());
+    }
+
+    #[test]
+    fn progress_messages() {
+        let prompt = json!({ "3": { "_meta": { "title": "Sampler" } } });
+        let executing = json!({ "type": "executing", "data": { "node": "3" } });
+        assert_eq!(
+            ComfyProgress::from_message(&executing, &prompt),
+            Some(ComfyProgress::Executing {
+                node: "3".to_string(),
+                title: Some("Sampler".to_string())
+            })
+        );
+        // a null node means the prompt is done, handled by run_prompt
+        let done = json!({ "type": "executing", "data": { "node": null } });
+        assert_eq!(ComfyProgress::from_message(&done, &prompt), None);
+        let cached = json!({ "type": "execution_cached", "data": { "nodes": ["4", "6"] } });
+        assert_eq!(
+            ComfyProgress::from_message(&cached, &prompt),
+            Some(ComfyProgress::Cached(vec![
+                "4".to_string(),
+                "6".to_string()
+            ]))
+        );
+        let step = json!({ "type": "progress", "data": { "node": "3", "value": 5, "max": 20 } });
+        assert_eq!(
+            ComfyProgress::from_message(&step, &prompt),
+            Some(ComfyProgress::Step {
+                node: "3".to_string(),
+                value: 5,
+                max: 20
+            })
+        );
+    }
+
+    #[test]
+    fn input_paths() {
+        let mut comfy = ComfyClient::default();
+        assert_eq!(comfy.input_path("car.glb"), "input/car.glb");
+        comfy.input_dir = "/srv/comfy/input/".to_string();
+        assert_eq!(comfy.input_path("car.glb"), "/srv/comfy/input/car.glb");
+        comfy.input_dir = String::new();
+        assert_eq!(comfy.input_path("car.glb"), "car.glb");
+    }
 }
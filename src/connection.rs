@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::StreamExt;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::AbortHandle;
+
+use crate::{fetch_models, fetch_object_info, ComfyClient, ComfyError, ComfyScheme};
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Hands websocket messages to whichever job is waiting on that prompt id
+#[derive(Clone, Default, Debug)]
+pub struct PromptRouter {
+    prompts: Arc<Mutex<HashMap<String, UnboundedSender<Value>>>>,
+    connected: Arc<AtomicBool>,
+}
+
+impl PromptRouter {
+    pub fn subscribe(&self, prompt_id: &str) -> PromptSubscription {
+        let (sender, receiver) = unbounded_channel();
+        self.prompts
+            .lock()
+            .unwrap()
+            .insert(prompt_id.to_string(), sender);
+        PromptSubscription {
+            router: self.clone(),
+            prompt_id: prompt_id.to_string(),
+            receiver,
+        }
+    }
+
+    /// prompt ids someone is still waiting on
+    pub fn pending(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn send(&self, prompt_id: &str, message: Value) {
+        if let Some(sender) = self.prompts.lock().unwrap().get(prompt_id) {
+            sender.send(message).ok();
+        }
+    }
+
+    /// messages without a prompt id (status, system stats) are dropped
+    pub fn route(&self, message: Value) {
+        if let Some(prompt_id) = message["data"]["prompt_id"].as_str().map(|s| s.to_string()) {
+            self.send(&prompt_id, message);
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+}
+
+/// Messages for one prompt, unsubscribes when dropped so cancelled jobs dont leak
+pub struct PromptSubscription {
+    router: PromptRouter,
+    prompt_id: String,
+    receiver: UnboundedReceiver<Value>,
+}
+
+impl PromptSubscription {
+    pub async fn recv(&mut self) -> Option<Value> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for PromptSubscription {
+    fn drop(&mut self) {
+        if let Ok(mut prompts) = self.router.prompts.lock() {
+            prompts.remove(&self.prompt_id);
+        }
+    }
+}
+
+/// Cancels the prompt on the server when dropped before [`PromptGuard::disarm`],
+/// so aborting a task waiting on a prompt also stops the prompt
+pub struct PromptGuard {
+    comfy: ComfyClient,
+    prompt_id: Option<String>,
+    runtime: Handle,
+}
+
+impl PromptGuard {
+    /// must be created inside the tokio runtime
+    pub fn new(comfy: &ComfyClient, prompt_id: String) -> Self {
+        Self {
+            comfy: comfy.clone(),
+            prompt_id: Some(prompt_id),
+            runtime: Handle::current(),
+        }
+    }
+
+    /// the server can answer with another id than the one we asked for
+    pub fn set_prompt_id(&mut self, prompt_id: String) {
+        self.prompt_id = Some(prompt_id);
+    }
+
+    /// the prompt finished or failed, nothing left to cancel
+    pub fn disarm(&mut self) {
+        self.prompt_id = None;
+    }
+}
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        let Some(prompt_id) = self.prompt_id.take() else {
+            return;
+        };
+        let comfy = self.comfy.clone();
+        self.runtime.spawn(async move {
+            match comfy.cancel_prompt(&prompt_id).await {
+                Ok(_) => info!("Cancelled prompt {}", prompt_id),
+                Err(err) => error!("Failed to cancel prompt {}: {}", prompt_id, err),
+            }
+        });
+    }
+}
+
+/// Handle to the long lived websocket task
+#[derive(Resource, Default)]
+pub struct ComfyConnection {
+    task: Option<AbortHandle>,
+    /// host and scheme the task connects to
+    target: Option<(String, ComfyScheme)>,
+}
+
+/// (re)starts the websocket task when the host or scheme change,
+/// other settings are picked up by the next request
+pub fn connect_comfy(
+    comfy: Res<ComfyClient>,
+    runtime: Res<TokioTasksRuntime>,
+    mut connection: ResMut<ComfyConnection>,
+) {
+    let target = Some((comfy.host().to_string(), comfy.scheme));
+    if connection.task.is_some() && connection.target == target {
+        return;
+    }
+    if let Some(task) = connection.task.take() {
+        task.abort();
+    }
+    let comfy = comfy.clone();
+    let task = runtime.spawn_background_task(async move |mut ctx| {
+        comfy.run_connection(&mut ctx).await;
+    });
+    connection.task = Some(task.abort_handle());
+    connection.target = target;
+}
+
+impl ComfyClient {
+    /// Keeps one websocket open, routing messages by prompt id and
+    /// reconnecting with backoff if ComfyUI goes away
+    pub async fn run_connection(&self, ctx: &mut TaskContext) {
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            self.router.set_connected(false);
+            match self.connect().await {
+                Ok(mut ws) => {
+                    info!("Connected to ComfyUI at {}", self.host());
+                    self.router.set_connected(true);
+                    backoff = RECONNECT_MIN;
+                    fetch_object_info(self, ctx).await;
+                    fetch_models(self, ctx).await;
+
+                    // anything that finished while we were away
+                    for prompt_id in self.router.pending() {
+                        if let Err(err) = self.recover_prompt(&prompt_id).await {
+                            warn!("Failed to check history for {}: {}", prompt_id, err);
+                        }
+                    }
+
+                    while let Some(msg) = ws.next().await {
+                        match msg {
+                            Ok(msg) if msg.is_text() => {
+                                let Ok(text) = msg.into_text() else {
+                                    continue;
+                                };
+                                match serde_json::from_str::<Value>(&text) {
+                                    Ok(message) => self.router.route(message),
+                                    Err(err) => warn!("Bad message from ComfyUI: {}", err),
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(err) => {
+                                warn!("ComfyUI websocket error: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    warn!("Disconnected from ComfyUI");
+                }
+                Err(err) => {
+                    warn!("Failed to connect to ComfyUI, retrying in {:?}: {}", backoff, err);
+                }
+            }
+            self.router.set_connected(false);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    /// Checks `/history` for a prompt and replays how it ended to whoever is waiting,
+    /// false if it isnt in the history, ex still queued or running
+    pub async fn recover_prompt(&self, prompt_id: &str) -> Result<bool, ComfyError> {
+        let history = self.get_history(prompt_id).await?;
+        let entry = &history[prompt_id];
+        if entry.is_null() {
+            return Ok(false);
+        }
+
+        // status messages are [type, data] pairs
+        if let Some(messages) = entry["status"]["messages"].as_array() {
+            for message in messages {
+                let kind = message[0].as_str().unwrap_or_default();
+                if kind == "execution_error" || kind == "execution_interrupted" {
+                    self.router.send(
+                        prompt_id,
+                        json!({ "type": kind, "data": message[1].clone() }),
+                    );
+                    return Ok(true);
+                }
+            }
+        }
+
+        self.router.send(
+            prompt_id,
+            json!({ "type": "executing", "data": { "node": null, "prompt_id": prompt_id } }),
+        );
+        Ok(true)
+    }
+}
@@ -1,94 +1,522 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use bevy::asset::RenderAssetUsages;
+use bevy::image::{CompressedImageFormats, ImageSampler, ImageType};
+use bevy::picking::mesh_picking::ray_cast::{MeshRayCast, MeshRayCastSettings};
 use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
 use bevy_enhanced_input::events::Fired;
 //use billboard::prelude::*;
-use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
+use wl_clipboard_rs::paste::{get_contents, get_mime_types, ClipboardType, MimeType, Seat};
+
+use crate::{
+    create_unique_name, png_size, GenerationMetadata, ImportAction, PasteAction, Prefab, Workflow,
+};
 
-use crate::{PasteAction, Prefab, Workflow};
+/// image types we can paste, best first, anything but png is converted to png
+const IMAGE_MIME_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/webp", "image/bmp"];
+const URI_LIST: &str = "text/uri-list";
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "bmp"];
+const MODEL_EXTENSIONS: [&str; 3] = ["glb", "gltf", "obj"];
+/// spacing between prefabs when several files are pasted or dropped at once
+const PASTE_SPACING: f32 = 2.0;
 
-pub fn paste( 
+pub fn paste(
     _trigger: Trigger<Fired<PasteAction>>,
     mut commands: Commands,
-    camera_transform: Single<&Transform, With<Camera>>,  
+    camera_transform: Single<&Transform, With<Camera>>,
+    prefabs: Query<&Prefab>,
 ) {
     info!("Paste event triggered");
+    let pos = camera_transform.translation + camera_transform.forward() * 4.0;
+    let right = camera_transform.right() * PASTE_SPACING;
+    let mut names = taken_names(&prefabs);
+
+    let offered = match get_mime_types(ClipboardType::Regular, Seat::Unspecified) {
+        Ok(offered) => offered,
+        Err(err) => {
+            error!("Error pasting: {:?}", err);
+            return;
+        }
+    };
+
+    // raw image data, ex copied from a browser or a screenshot tool
+    if let Some(&mime) = IMAGE_MIME_TYPES.iter().find(|m| offered.contains(**m)) {
+        match read_clipboard(MimeType::Specific(mime)) {
+            Ok(data) => import_image(
+                &mut commands,
+                &data,
+                mime,
+                "clipboard_image",
+                &mut names,
+                pos,
+            ),
+            Err(err) => error!("Error pasting {}: {}", mime, err),
+        }
+        return;
+    }
+
+    // files copied in a file manager, or a single path copied as text
+    let paths = if offered.contains(URI_LIST) {
+        read_clipboard(MimeType::Specific(URI_LIST))
+            .map(|data| parse_uri_list(&String::from_utf8_lossy(&data)))
+    } else {
+        read_clipboard(MimeType::Text).map(|data| {
+            let clipboard = String::from_utf8_lossy(&data).trim().to_string();
+            info!("Clipboard contents: {:?}", &clipboard);
+            vec![PathBuf::from(clipboard)]
+        })
+    };
+    let paths = match paths {
+        Ok(paths) => paths,
+        Err(err) => {
+            error!("Error pasting: {}", err);
+            return;
+        }
+    };
+    import_row(&mut commands, &paths, &mut names, pos, right);
+}
+
+fn read_clipboard(mime: MimeType) -> Result<Vec<u8>, String> {
     use std::io::Read;
+    let (mut pipe, _) = get_contents(ClipboardType::Regular, Seat::Unspecified, mime)
+        .map_err(|err| err.to_string())?;
+    let mut contents = vec![];
+    pipe.read_to_end(&mut contents)
+        .map_err(|err| err.to_string())?;
+    Ok(contents)
+}
 
-    let result = get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Any);
-    match result {
-        Ok((mut pipe, _)) => {
-            let mut contents = vec![];
-            if let Ok(_) = pipe.read_to_end(&mut contents) {
-                let clipboard = String::from_utf8_lossy(&contents).to_string();
-                info!("Clipboard contents: {:?}", &clipboard);
-
-                if clipboard.ends_with(".png") {
-                    let path = std::path::Path::new(&clipboard);
-                    let file_name = path
-                        .file_name()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("clipboard_image.png")
-                        .to_string();
-                    let file_stem = path
-                        .file_stem()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("clipboard_image")
-                        .to_string();
-
-                    let new_path = std::path::Path::new("assets/ref/").join(&file_name);
-
-                    let new_asset_path = format!("ref/{}", &file_name);
-                    // copy to ref assets
-                    std::fs::copy(&path, &new_path).expect("Failed to copy file");
-
-                    let pos = camera_transform.translation + camera_transform.forward() * 4.0;
-                    commands.spawn((
-                        Transform::from_translation(pos),
-                        Name::new(file_stem.clone()),
-                        Prefab {
-                            name: file_stem,
-                            workflow: Workflow::StaticImage {
-                                image: Some(new_asset_path),
-                            },
-                        },
-                    ));
-                } else {
-                    warn!("Clipboard contents not an image: {:?}", &clipboard);
-                }
-            }
+/// local files in a text/uri-list, comments and remote uris are skipped
+fn parse_uri_list(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| url::Url::parse(line).ok()?.to_file_path().ok())
+        .collect()
+}
+
+/// prefab names and files already in ref, so pasted images never overwrite anything
+fn taken_names(prefabs: &Query<&Prefab>) -> Vec<String> {
+    let files = std::fs::read_dir("assets/ref")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .map(|s| s.to_string())
+        });
+    prefabs
+        .iter()
+        .map(|p| p.name.clone())
+        .chain(files)
+        .collect()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// an image or model we know how to import
+pub fn is_importable(path: &Path) -> bool {
+    let ext = extension(path);
+    IMAGE_EXTENSIONS.contains(&ext.as_str()) || MODEL_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// imports files side by side along `right`, centered on `pos`
+fn import_row(
+    commands: &mut Commands,
+    paths: &[PathBuf],
+    names: &mut Vec<String>,
+    pos: Vec3,
+    right: Vec3,
+) {
+    let left = -(paths.len().saturating_sub(1) as f32) * 0.5;
+    for (i, path) in paths.iter().enumerate() {
+        import_file(commands, path, names, pos + right * (left + i as f32));
+    }
+}
+
+/// imports an image or model file from anywhere on disk
+fn import_file(commands: &mut Commands, path: &Path, names: &mut Vec<String>, pos: Vec3) {
+    let ext = extension(path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("clipboard_image");
+    if MODEL_EXTENSIONS.contains(&ext.as_str()) {
+        import_model(commands, path, stem, &ext, names, pos);
+        return;
+    }
+    let mime = match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => {
+            warn!("Not an image or model: {:?}", path);
+            return;
         }
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
         Err(err) => {
-            error!("Error pasting: {:?}", err);
+            error!("Failed to read {:?}: {}", path, err);
+            return;
+        }
+    };
+    import_image(commands, &data, mime, stem, names, pos);
+}
+
+/// copies a model to ref and spawns a static model prefab for it
+fn import_model(
+    commands: &mut Commands,
+    path: &Path,
+    stem: &str,
+    ext: &str,
+    names: &mut Vec<String>,
+    pos: Vec3,
+) {
+    let name = create_unique_name(&stem.to_string(), names.clone());
+    names.push(name.clone());
+    let result = if ext == "gltf" {
+        copy_gltf(path, &name)
+    } else if ext == "obj" {
+        copy_obj(path, &name)
+    } else {
+        let model = format!("ref/{}.{}", name, ext);
+        std::fs::copy(path, Path::new("assets").join(&model)).map(|_| model)
+    };
+    let model = match result {
+        Ok(model) => model,
+        Err(err) => {
+            error!("Failed to import {:?}: {}", path, err);
+            return;
         }
+    };
+
+    commands.spawn((
+        Transform::from_translation(pos),
+        Name::new(name.clone()),
+        Prefab {
+            name,
+            workflow: Workflow::StaticModel { model: Some(model) },
+            ..default()
+        },
+    ));
+}
+
+/// copies a .gltf into ref/<name>/ along with the buffers and textures it points to
+fn copy_gltf(path: &Path, name: &str) -> std::io::Result<String> {
+    let source_dir = path.parent().unwrap_or(Path::new(".")).canonicalize()?;
+    let dir = Path::new("ref").join(name);
+    std::fs::create_dir_all(Path::new("assets").join(&dir))?;
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(path)?).map_err(std::io::Error::other)?;
+    // uris are relative to the gltf and percent encoded, embedded data needs no copy
+    let base = url::Url::from_directory_path(&source_dir)
+        .map_err(|_| std::io::Error::other("gltf folder is not absolute"))?;
+    let uris = ["buffers", "images"]
+        .iter()
+        .flat_map(|key| json[*key].as_array().into_iter().flatten())
+        .filter_map(|v| v["uri"].as_str())
+        .filter(|uri| !uri.starts_with("data:"));
+    for uri in uris {
+        let Some(source) = base.join(uri).ok().and_then(|u| u.to_file_path().ok()) else {
+            warn!("Skipping gltf uri {}", uri);
+            continue;
+        };
+        copy_next_to(&source, &source_dir, &dir)?;
     }
+
+    let model = dir.join(format!("{}.gltf", name));
+    std::fs::copy(path, Path::new("assets").join(&model))?;
+    Ok(model.to_string_lossy().to_string())
 }
 
-// TODO: doesnt work on wayland
-pub fn file_drop(mut evr_dnd: EventReader<FileDragAndDrop>) {
-    for ev in evr_dnd.read() {
-        // TODO: on wayland this event never fires
-        dbg!("File drop event never fire!!!!!!");
-        match ev {
-            FileDragAndDrop::DroppedFile { window, path_buf } => {
-                info!("Dropped file: {:?} at {:?}", path_buf, window);
-                //let texture_handle = asset_server.load(path_buf.to_str().unwrap().to_string());
-
-                // commands.spawn(
-                //     SpriteBundle {
-                //         texture: texture_handle,
-                //         transform: Transform::from_xyz(world_cursor.0.x, world_cursor.0.y, 0.0),
-                //         ..default()
-                //     });
-            }
-            FileDragAndDrop::HoveredFile {
-                window: _,
-                path_buf: _,
-            } => {
-                // On wayland this sometimes prints multiple times for one drop
-                info!("Hovered file");
+/// material statements that name a texture file
+const MTL_MAPS: &[&str] = &[
+    "map_Ka", "map_Kd", "map_Ks", "map_Ke", "map_Ns", "map_d", "map_Bump", "map_bump", "bump",
+    "disp", "decal", "norm", "map_Pr", "map_Pm", "map_Ps",
+];
+
+/// copies an .obj into ref/<name>/ along with its mtllib files and the textures they use,
+/// paths are relative to the obj like bevy_obj loads them
+fn copy_obj(path: &Path, name: &str) -> std::io::Result<String> {
+    let source_dir = path.parent().unwrap_or(Path::new(".")).canonicalize()?;
+    let dir = Path::new("ref").join(name);
+    std::fs::create_dir_all(Path::new("assets").join(&dir))?;
+
+    let obj = String::from_utf8_lossy(&std::fs::read(path)?).to_string();
+    for mtl in statement_files(&obj, &["mtllib"]) {
+        let source = source_dir.join(mtl);
+        if !copy_next_to(&source, &source_dir, &dir)? {
+            continue;
+        }
+        let material = String::from_utf8_lossy(&std::fs::read(&source)?).to_string();
+        for texture in statement_files(&material, MTL_MAPS) {
+            copy_next_to(&source_dir.join(texture), &source_dir, &dir)?;
+        }
+    }
+
+    let model = dir.join(format!("{}.obj", name));
+    std::fs::copy(path, Path::new("assets").join(&model))?;
+    Ok(model.to_string_lossy().to_string())
+}
+
+/// file names after the given obj or mtl statements, options like `-bm 0.5` come before
+/// the name so those take the last word, otherwise the rest of the line to allow spaces
+fn statement_files<'a>(text: &'a str, statements: &[&str]) -> Vec<&'a str> {
+    text.lines()
+        .filter_map(|line| {
+            let (statement, rest) = line.trim().split_once(char::is_whitespace)?;
+            statements.contains(&statement).then_some(rest.trim())
+        })
+        .filter_map(|rest| {
+            if rest.split_whitespace().any(|word| word.starts_with('-')) {
+                rest.split_whitespace().last()
+            } else {
+                Some(rest).filter(|r| !r.is_empty())
             }
-            FileDragAndDrop::HoveredFileCanceled { window: _ } => {
-                info!("File canceled!");
+        })
+        .collect()
+}
+
+/// copies a file the model points to into `dir` at the same relative path,
+/// false if it is missing or outside the model's folder
+fn copy_next_to(source: &Path, source_dir: &Path, dir: &Path) -> std::io::Result<bool> {
+    // resolves any .. so they cant climb out of the folder
+    let Some(source) = source.canonicalize().ok().filter(|s| s.is_file()) else {
+        warn!("Skipping missing {:?}", source);
+        return Ok(false);
+    };
+    let Ok(relative) = source.strip_prefix(source_dir) else {
+        warn!("Skipping {:?}, it is outside the model's folder", source);
+        return Ok(false);
+    };
+    let target = Path::new("assets").join(dir).join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(&source, target)?;
+    Ok(true)
+}
+
+/// writes an image to ref/<unique name>.png and spawns a prefab for it, pngs saved by
+/// ComfyUI become a TextToImage prefab with the settings they were made with
+fn import_image(
+    commands: &mut Commands,
+    data: &[u8],
+    mime: &str,
+    stem: &str,
+    names: &mut Vec<String>,
+    pos: Vec3,
+) {
+    let name = create_unique_name(&stem.to_string(), names.clone());
+    names.push(name.clone());
+    let new_asset_path = format!("ref/{}.png", &name);
+    let new_path = Path::new("assets").join(&new_asset_path);
+
+    // pngs are kept byte for byte so their metadata survives
+    let png = if mime == "image/png" {
+        data.to_vec()
+    } else {
+        match to_png(data, mime) {
+            Ok(png) => png,
+            Err(err) => {
+                error!("Failed to decode {}: {}", mime, err);
+                return;
             }
         }
+    };
+    if let Err(err) = std::fs::write(&new_path, png) {
+        error!("Failed to write {:?}: {}", new_path, err);
+        return;
     }
+
+    let workflow = GenerationMetadata::read(&new_path)
+        .ok()
+        .and_then(|m| m.text_to_image(new_asset_path.clone(), png_size(&new_path)))
+        .unwrap_or(Workflow::StaticImage {
+            image: Some(new_asset_path),
+        });
+    if matches!(workflow, Workflow::TextToImage { .. }) {
+        info!("Rebuilt generation settings for {}", name);
+    }
+
+    commands.spawn((
+        Transform::from_translation(pos),
+        Name::new(name.clone()),
+        Prefab {
+            name,
+            workflow,
+            ..default()
+        },
+    ));
+}
+
+/// decodes any image type bevy can load and encodes it as rgba png
+fn to_png(data: &[u8], mime: &str) -> Result<Vec<u8>, String> {
+    let image = Image::from_buffer(
+        data,
+        ImageType::MimeType(mime),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    )
+    .map_err(|err| err.to_string())?;
+    let size = image.size();
+    let pixels = image
+        .convert(TextureFormat::Rgba8UnormSrgb)
+        .and_then(|image| image.data)
+        .ok_or("unsupported pixel format")?;
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, size.x, size.y);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|err| err.to_string())?;
+    Ok(png_data)
+}
+
+/// where a ray from the cursor meets the ground or something on the board, lifted off it so
+/// the new prefab isn't hidden inside, empty space falls back to the board plane
+fn drop_point(ray_cast: &mut MeshRayCast, ray: Ray3d) -> Option<Vec3> {
+    if let Some((_, hit)) = ray_cast
+        .cast_ray(ray, &MeshRayCastSettings::default())
+        .first()
+    {
+        // images stand on the ground instead of lying in it
+        let lift = if hit.normal.y > 0.5 {
+            Vec3::Y
+        } else {
+            hit.normal * 0.1
+        };
+        return Some(hit.point + lift);
+    }
+    ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Z))
+        .map(|distance| ray.get_point(distance))
+}
+
+/// imports everything dropped in a frame as one row under the cursor,
+/// winit never delivers drops on wayland, the Import dialog covers that
+pub fn file_drop(
+    mut evr_dnd: EventReader<FileDragAndDrop>,
+    mut commands: Commands,
+    windows: Query<&Window>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    mut ray_cast: MeshRayCast,
+    prefabs: Query<&Prefab>,
+) {
+    let mut dropped = Vec::new();
+    let mut drop_window = None;
+    for ev in evr_dnd.read() {
+        if let FileDragAndDrop::DroppedFile { window, path_buf } = ev {
+            info!("Dropped file: {:?}", path_buf);
+            dropped.push(path_buf.clone());
+            drop_window = Some(*window);
+        }
+    }
+    if dropped.is_empty() {
+        return;
+    }
+
+    let (camera, camera_transform) = *camera;
+    let pos = drop_window
+        .and_then(|window| windows.get(window).ok())
+        .and_then(|window| window.cursor_position())
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| drop_point(&mut ray_cast, ray))
+        .unwrap_or(camera_transform.translation() + camera_transform.forward() * 4.0);
+    import_row(
+        &mut commands,
+        &dropped,
+        &mut taken_names(&prefabs),
+        pos,
+        camera_transform.right() * PASTE_SPACING,
+    );
+}
+
+/// files picked in the Import dialog
+#[derive(Event)]
+pub struct ImportFiles(pub Vec<PathBuf>);
+
+pub fn import_files(
+    mut events: EventReader<ImportFiles>,
+    mut commands: Commands,
+    camera_transform: Single<&Transform, With<Camera>>,
+    prefabs: Query<&Prefab>,
+) {
+    let pos = camera_transform.translation + camera_transform.forward() * 4.0;
+    let right = camera_transform.right() * PASTE_SPACING;
+    let mut names = taken_names(&prefabs);
+    for ImportFiles(paths) in events.read() {
+        import_row(&mut commands, paths, &mut names, pos, right);
+    }
+}
+
+/// File browser for importing when drag and drop doesn't work
+#[derive(Resource, Debug)]
+pub struct ImportDialog {
+    pub open: bool,
+    /// folder being browsed
+    pub dir: PathBuf,
+    /// sub folders and importable files of `dir`, hidden ones skipped
+    pub folders: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub selected: BTreeSet<PathBuf>,
+}
+
+impl Default for ImportDialog {
+    fn default() -> Self {
+        let dir = std::env::var("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("."));
+        let mut dialog = Self {
+            open: false,
+            dir: PathBuf::new(),
+            folders: Vec::new(),
+            files: Vec::new(),
+            selected: BTreeSet::new(),
+        };
+        dialog.set_dir(dir);
+        dialog
+    }
+}
+
+impl ImportDialog {
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        let mut entries = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        let (folders, files): (Vec<_>, Vec<_>) = entries.into_iter().partition(|p| p.is_dir());
+        self.folders = folders;
+        self.files = files.into_iter().filter(|p| is_importable(p)).collect();
+        self.selected.clear();
+        self.dir = dir;
+    }
+}
+
+pub fn open_import(_trigger: Trigger<Fired<ImportAction>>, mut dialog: ResMut<ImportDialog>) {
+    dialog.open = true;
 }
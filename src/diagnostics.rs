@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TaskContext;
+use serde_json::Value;
+
+use crate::{ComfyClient, WorkflowTemplate, WorkflowTemplates};
+
+/// ComfyUI's `/object_info`, every node class it has with its inputs
+/// None until connected
+#[derive(Resource, Default)]
+pub struct ObjectInfo(pub Option<Value>);
+
+impl ObjectInfo {
+    /// choices of a combo input, ex KSampler's sampler_name, empty if unknown
+    pub fn options(&self, class_type: &str, input: &str) -> Vec<String> {
+        let Some(info) = &self.0 else {
+            return Vec::new();
+        };
+        let inputs = &info[class_type]["input"];
+        let spec = if inputs["required"][input].is_null() {
+            &inputs["optional"][input]
+        } else {
+            &inputs["required"][input]
+        };
+        combo_options(spec)
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|o| o.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Problems found in each loaded template, by template name
+#[derive(Resource, Default, Debug)]
+pub struct WorkflowDiagnostics(pub BTreeMap<String, Vec<Diagnostic>>);
+
+impl WorkflowDiagnostics {
+    pub fn count(&self) -> usize {
+        self.0.values().map(|d| d.len()).sum()
+    }
+
+    /// problems in the given templates
+    pub fn for_templates<'a>(&'a self, names: &'a [&str]) -> impl Iterator<Item = &'a Diagnostic> {
+        names
+            .iter()
+            .filter_map(|name| self.0.get(*name))
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Diagnostic {
+    /// custom node not installed
+    MissingNode { node: String, class_type: String },
+    /// node doesnt have this input, likely a different version of the custom node
+    UnknownInput {
+        node: String,
+        class_type: String,
+        input: String,
+    },
+    MissingInput {
+        node: String,
+        class_type: String,
+        input: String,
+    },
+    /// checkpoint, lora, vae... not in the models folder
+    MissingModel {
+        node: String,
+        class_type: String,
+        input: String,
+        value: String,
+    },
+    InvalidValue {
+        node: String,
+        class_type: String,
+        input: String,
+        value: String,
+    },
+    WrongType {
+        node: String,
+        class_type: String,
+        input: String,
+        expected: String,
+    },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::MissingNode { node, class_type } => {
+                write!(f, "Missing node {} (node {})", class_type, node)
+            }
+            Diagnostic::UnknownInput {
+                node,
+                class_type,
+                input,
+            } => write!(f, "{} (node {}) has no input {}", class_type, node, input),
+            Diagnostic::MissingInput {
+                node,
+                class_type,
+                input,
+            } => write!(f, "{} (node {}) is missing input {}", class_type, node, input),
+            Diagnostic::MissingModel {
+                node,
+                class_type,
+                input,
+                value,
+            } => write!(
+                f,
+                "Missing model {} for {}.{} (node {})",
+                value, class_type, input, node
+            ),
+            Diagnostic::InvalidValue {
+                node,
+                class_type,
+                input,
+                value,
+            } => write!(
+                f,
+                "Invalid value {} for {}.{} (node {})",
+                value, class_type, input, node
+            ),
+            Diagnostic::WrongType {
+                node,
+                class_type,
+                input,
+                expected,
+            } => write!(
+                f,
+                "{}.{} (node {}) should be {}",
+                class_type, input, node, expected
+            ),
+        }
+    }
+}
+
+const MODEL_EXTENSIONS: [&str; 8] = [
+    ".safetensors",
+    ".ckpt",
+    ".pt",
+    ".pth",
+    ".bin",
+    ".gguf",
+    ".sft",
+    ".onnx",
+];
+
+fn is_model_file(value: &str) -> bool {
+    let value = value.to_lowercase();
+    MODEL_EXTENSIONS.iter().any(|ext| value.ends_with(ext))
+}
+
+/// combo options of an input spec, old `[[options], {}]` or new `["COMBO", {options}]` format
+fn combo_options(spec: &Value) -> Option<&Vec<Value>> {
+    if spec[0] == "COMBO" {
+        spec[1]["options"].as_array()
+    } else {
+        spec[0].as_array()
+    }
+}
+
+/// Checks every node of an api format workflow against `/object_info`,
+/// values of inputs matching `skip(node, input)` arent checked
+pub fn validate_workflow(
+    workflow: &Value,
+    info: &Value,
+    skip: impl Fn(&str, &str) -> bool,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let Some(nodes) = workflow.as_object() else {
+        return diagnostics;
+    };
+    for (node, n) in nodes.iter() {
+        let node = node.clone();
+        let class_type = n["class_type"].as_str().unwrap_or_default().to_string();
+        let def = &info[class_type.as_str()];
+        if def.is_null() {
+            diagnostics.push(Diagnostic::MissingNode { node, class_type });
+            continue;
+        }
+        let required = def["input"]["required"].as_object();
+        let optional = def["input"]["optional"].as_object();
+        let inputs = n["inputs"].as_object();
+
+        if let Some(required) = required {
+            for input in required.keys() {
+                if !inputs.is_some_and(|i| i.contains_key(input)) {
+                    diagnostics.push(Diagnostic::MissingInput {
+                        node: node.clone(),
+                        class_type: class_type.clone(),
+                        input: input.clone(),
+                    });
+                }
+            }
+        }
+
+        for (input, value) in inputs.into_iter().flatten() {
+            let spec = required
+                .and_then(|r| r.get(input))
+                .or_else(|| optional.and_then(|o| o.get(input)));
+            let Some(spec) = spec else {
+                diagnostics.push(Diagnostic::UnknownInput {
+                    node: node.clone(),
+                    class_type: class_type.clone(),
+                    input: input.clone(),
+                });
+                continue;
+            };
+            // links are [node id, output index], checked by ComfyUI
+            if value.is_array() || skip(&node, input) {
+                continue;
+            }
+
+            if let Some(options) = combo_options(spec) {
+                if options.contains(value) {
+                    continue;
+                }
+                let value = value
+                    .as_str()
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| value.to_string());
+                let node = node.clone();
+                let class_type = class_type.clone();
+                let input = input.clone();
+                diagnostics.push(if is_model_file(&value) {
+                    Diagnostic::MissingModel {
+                        node,
+                        class_type,
+                        input,
+                        value,
+                    }
+                } else {
+                    Diagnostic::InvalidValue {
+                        node,
+                        class_type,
+                        input,
+                        value,
+                    }
+                });
+                continue;
+            }
+
+            let expected = spec[0].as_str().unwrap_or_default();
+            let ok = match expected {
+                "INT" => value.is_i64() || value.is_u64(),
+                "FLOAT" => value.is_number(),
+                "STRING" => value.is_string(),
+                "BOOLEAN" => value.is_boolean(),
+                // custom types can only come from links
+                _ => true,
+            };
+            if !ok {
+                diagnostics.push(Diagnostic::WrongType {
+                    node: node.clone(),
+                    class_type: class_type.clone(),
+                    input: input.clone(),
+                    expected: expected.to_string(),
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Fetches `/object_info`, called on every (re)connect since nodes can be installed meanwhile
+pub async fn fetch_object_info(comfy: &ComfyClient, ctx: &mut TaskContext) {
+    match comfy.get_object_info().await {
+        Ok(info) => {
+            ctx.run_on_main_thread(move |ctx| {
+                ctx.world.insert_resource(ObjectInfo(Some(info)));
+            })
+            .await;
+        }
+        Err(err) => warn!("Failed to get node info from ComfyUI: {}", err),
+    }
+}
+
+/// rechecks every template when the node info or a template changes
+pub fn validate_workflows(
+    info: Res<ObjectInfo>,
+    templates: Res<WorkflowTemplates>,
+    template_assets: Res<Assets<WorkflowTemplate>>,
+    mut diagnostics: ResMut<WorkflowDiagnostics>,
+) {
+    let Some(info) = &info.0 else {
+        return;
+    };
+    diagnostics.0.clear();
+    for name in templates.0.keys() {
+        let Ok(template) = templates.get(name, &template_assets) else {
+            continue;
+        };
+        let found = validate_workflow(&template.workflow, info, |node, input| {
+            template.overwrites(node, input)
+        });
+        for diagnostic in found.iter() {
+            warn!("{}: {}", name, diagnostic);
+        }
+        diagnostics.0.insert(name.clone(), found);
+    }
+}
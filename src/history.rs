@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ComfyError, OutputKind, Prefab, RefreshImage, RefreshModel, WorkflowTemplate};
+
+/// where versions are kept, relative to assets
+const HISTORY_DIR: &str = "ref/history";
+
+/// A generated image or model kept in ref/history, newest last on the prefab
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct Version {
+    /// copy of the output, relative to assets
+    pub file: String,
+    pub output: OutputKind,
+    /// what made it, ex "Image", "Shape" or "Inpaint"
+    pub label: String,
+    pub seed: Option<u64>,
+    pub prompt: Option<String>,
+    /// the workflow exactly as queued, saved next to the file, None for files we didnt generate
+    pub workflow: Option<String>,
+    /// seconds the generation took
+    pub duration: f32,
+    /// unix seconds
+    pub timestamp: u64,
+}
+
+/// copies a just written output to the history with what made it,
+/// the version is added to the prefab on the main thread with [`AddVersion`]
+pub async fn record_version(
+    template: &WorkflowTemplate,
+    workflow: &Value,
+    path: &Path,
+    output: OutputKind,
+    label: &str,
+    start: Instant,
+) -> Result<Version, ComfyError> {
+    let (file, timestamp) = copy_to_history(path).await?;
+    // no asset loader claims this extension, the json loader would try to load it otherwise
+    let workflow_file = file.with_extension("workflow.snapshot");
+    let json = serde_json::to_vec_pretty(workflow).unwrap_or_default();
+    tokio::fs::write(Path::new("assets").join(&workflow_file), json).await?;
+
+    Ok(Version {
+        file: file.to_string_lossy().to_string(),
+        output,
+        label: label.to_string(),
+        seed: template.get(workflow, "seed").and_then(|v| v.as_u64()),
+        prompt: template
+            .get(workflow, "prompt")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        workflow: Some(workflow_file.to_string_lossy().to_string()),
+        duration: start.elapsed().as_secs_f32(),
+        timestamp,
+    })
+}
+
+/// keeps a file that is about to be overwritten but was never recorded, ex a pasted image
+pub async fn record_original(
+    path: &Path,
+    output: OutputKind,
+) -> Result<Option<Version>, ComfyError> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(None);
+    }
+    let (file, timestamp) = copy_to_history(path).await?;
+    Ok(Some(Version {
+        file: file.to_string_lossy().to_string(),
+        output,
+        label: "Original".to_string(),
+        seed: None,
+        prompt: None,
+        workflow: None,
+        duration: 0.0,
+        timestamp,
+    }))
+}
+
+/// copies to ref/history/<stem>_<millis>.<ext>, returns the copy relative to assets
+async fn copy_to_history(path: &Path) -> Result<(PathBuf, u64), ComfyError> {
+    tokio::fs::create_dir_all(Path::new("assets").join(HISTORY_DIR)).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let file = Path::new(HISTORY_DIR).join(format!(
+        "{}_{}.{}",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        now.as_millis(),
+        path.extension().unwrap_or_default().to_string_lossy()
+    ));
+    tokio::fs::copy(path, Path::new("assets").join(&file)).await?;
+    Ok((file, now.as_secs()))
+}
+
+/// adds a version to the prefab's history
+#[derive(Event)]
+pub struct AddVersion(pub Version);
+
+pub fn on_add_version(trigger: Trigger<AddVersion>, mut query: Query<&mut Prefab>) {
+    if let Ok(mut prefab) = query.get_mut(trigger.target()) {
+        prefab.history.push(trigger.0.clone());
+    }
+}
+
+/// copies a version back over the prefab's image or model, the history is left as is
+#[derive(Event)]
+pub struct Revert(pub usize);
+
+pub fn on_revert(
+    trigger: Trigger<Revert>,
+    query: Query<&Prefab>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let Ok(prefab) = query.get(e) else {
+        return;
+    };
+    let Some(version) = prefab.history.get(trigger.0) else {
+        return;
+    };
+    // a prefab that never had this output gets the version under its own name
+    let (current, ext) = match version.output {
+        OutputKind::Image => (prefab.workflow.image(), "png"),
+        OutputKind::Model => (prefab.workflow.model(), "glb"),
+    };
+    let current = current
+        .cloned()
+        .unwrap_or_else(|| format!("ref/{}.{}", prefab.name, ext));
+    // ex a generated glb can't be copied over an imported obj
+    let extension = |path: &str| Path::new(path).extension().map(|e| e.to_ascii_lowercase());
+    if extension(&version.file) != extension(&current) {
+        error!(
+            "Can't revert {} to {}, the file types differ",
+            current, version.file
+        );
+        return;
+    }
+
+    if let Err(err) = std::fs::copy(
+        Path::new("assets").join(&version.file),
+        Path::new("assets").join(&current),
+    ) {
+        error!("Failed to revert to {}: {}", version.file, err);
+        return;
+    }
+    info!("Reverted {} to {}", prefab.name, version.file);
+    asset_server.reload(current.clone());
+    match version.output {
+        OutputKind::Image => commands.trigger_targets(RefreshImage(current), e),
+        OutputKind::Model => commands.trigger_targets(RefreshModel(current), e),
+    }
+}
+
+/// deletes every version's files, used when the prefab is deleted
+pub fn remove_history(history: &[Version]) {
+    for version in history {
+        for file in std::iter::once(&version.file).chain(version.workflow.iter()) {
+            std::fs::remove_file(Path::new("assets").join(file)).unwrap_or_default();
+        }
+    }
+}
@@ -6,6 +6,8 @@ mod assets;
 pub use assets::*;
 
 mod comfy;
+mod connection;
+pub use connection::*;
 use bevy_enhanced_input::prelude::*;
 use bevy_health_bar3d::prelude::*;
 
@@ -23,6 +25,28 @@ mod ui;
 pub use ui::*;
 mod progress;
 pub use progress::*;
+mod queue;
+pub use queue::*;
+mod template;
+pub use template::*;
+mod diagnostics;
+pub use diagnostics::*;
+mod models;
+pub use models::*;
+mod sampler;
+pub use sampler::*;
+mod mask;
+pub use mask::*;
+mod mesh;
+pub use mesh::*;
+mod retexture;
+pub use retexture::*;
+mod variants;
+pub use variants::*;
+mod history;
+pub use history::*;
+mod metadata;
+pub use metadata::*;
 
 use avian3d::prelude::*;
 use bevy::{
@@ -37,16 +61,17 @@ use bevy_rand::prelude::*;
 
 fn main() {
     let file_path = config_file_path();
-    let config = match std::fs::read(&file_path) {
-        Ok(s) => ron::de::from_bytes::<RefConfig>(&s).unwrap_or_default(),
-        Err(_) => {
-            error!("Failed to load config file: {:?}", &file_path);
-            RefConfig::default()
-        }
-    };
+    let config = RefConfig::load(&file_path);
+
+    let mut comfy = config.comfy.clone();
+    comfy.rebuild();
+
+    let queue = GenerationQueue::new(config.max_in_flight);
 
     let mut app = App::new();
     app.insert_resource(config)
+        .insert_resource(comfy)
+        .insert_resource(queue)
         .add_plugins((
             DefaultPlugins
                 .set(WindowPlugin {
@@ -67,6 +92,7 @@ fn main() {
                 // }),
             bevy_inspector_egui::quick::WorldInspectorPlugin::new(),
             MeshPickingPlugin,
+            bevy_obj::ObjPlugin,
             EnhancedInputPlugin,
             PhysicsPlugins::default(), // using for collision detection
             //bevy_inspector_egui::quick::FilterQueryInspectorPlugin::<With<Selected>>::default(),
@@ -74,7 +100,8 @@ fn main() {
             TokioTasksPlugin::default(),
             HealthBarPlugin::<WorkflowProgress>::default(),
             EntropyPlugin::<WyRand>::default(),
-            AppActionPlugin,        
+            AppActionPlugin,
+            WorkflowTemplatePlugin,
         ))
         .insert_resource(
             ColorScheme::<WorkflowProgress>::new()
@@ -84,17 +111,35 @@ fn main() {
             Update,
             ui_select.run_if(|query: Query<Entity, With<Selected>>| !query.is_empty()),
         )
+        .add_systems(Update, (ui_comfy, ui_queue, ui_diagnostics, ui_metadata, ui_import))
+        .init_resource::<ComfyConnection>()
+        .add_systems(Update, connect_comfy.run_if(resource_changed::<ComfyClient>))
         .init_resource::<SaveTimer>()
+        .init_resource::<ProgressChannel>()
+        .init_resource::<ObjectInfo>()
+        .init_resource::<WorkflowDiagnostics>()
+        .init_resource::<ComfyModels>()
+        .init_resource::<MetadataInspector>()
         .add_event::<Save>()
         .add_event::<SpawnPrefab>()
+        .add_event::<ImportFiles>()
+        .init_resource::<ImportDialog>()
         .add_systems(Startup, (setup, setup_ui))
         .add_systems(
             Update,
             (
                 spawn_prefab.run_if(on_event::<SpawnPrefab>),
                 update_progress,
+                start_generations,
+                update_plane_size,
+                update_variant_strips,
+                validate_workflows.run_if(
+                    resource_changed::<ObjectInfo>
+                        .or(on_event::<AssetEvent<WorkflowTemplate>>),
+                ),
                 autosave,
                 file_drop,
+                import_files.run_if(on_event::<ImportFiles>),
 
             ),
         )
@@ -103,6 +148,9 @@ fn main() {
         .register_type::<Prefab>()
         .register_type::<RefConfig>()
         .register_type::<PrefabConfig>()
+        .register_type::<GenerationFailed>()
+        .register_type::<GenerationQueue>()
+        .register_type::<ComfyClient>()
         .register_type::<SaveTimer>()
         .register_type::<Save>()
         .run();
@@ -224,6 +272,7 @@ fn spawn_prefab(camera_transform: Single<&mut Transform, With<Camera>>, mut comm
         Prefab {
             name: "Prefab".to_string(),
             workflow: Workflow::StaticImage { image: None },
+            ..default()
         },
         Transform::from_translation(pos),
     ));
@@ -0,0 +1,310 @@
+use bevy::asset::RenderAssetUsages;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_prng::WyRand;
+use bevy_rand::global::GlobalEntropy;
+use rand::Rng;
+
+use crate::{
+    GenerationFailed, GenerationJob, GenerationQueue, JobKind, PlaneSize, Prefab,
+    WorkflowProgress,
+};
+
+/// used until the prefab's image has loaded
+const DEFAULT_MASK_SIZE: u32 = 768;
+/// color the mask is shown in
+const MASK_COLOR: [u8; 4] = [255, 64, 64, 140];
+
+/// Mask being painted on a prefab's image plane, painted pixels get regenerated by Inpaint
+#[derive(Component, Debug)]
+pub struct MaskPainter {
+    /// same size as the prefab's image, alpha is what's painted
+    pub mask: Handle<Image>,
+    /// child quad showing the mask over the image
+    pub overlay: Entity,
+    /// brush radius in mask pixels
+    pub radius: f32,
+    pub erase: bool,
+    /// what to put in the masked area
+    pub prompt: String,
+    /// pixels the mask is grown by before inpainting, softens the seam
+    pub grow: u32,
+    /// last brush position of the current stroke, in mask pixels
+    last: Option<Vec2>,
+}
+
+/// Everything an inpaint job needs besides the prefab's own settings
+#[derive(Debug, Clone, Default, Reflect)]
+pub struct InpaintJob {
+    /// png, alpha 0 where painted, same size as the image
+    #[reflect(ignore)]
+    pub mask: Vec<u8>,
+    pub prompt: String,
+    pub seed: u64,
+    pub grow: u32,
+}
+
+/// starts painting a mask on the prefab, or stops and throws the mask away
+#[derive(Event)]
+pub struct ToggleMask;
+
+pub fn on_toggle_mask(
+    trigger: Trigger<ToggleMask>,
+    query: Query<(
+        &Prefab,
+        &Mesh3d,
+        &MeshMaterial3d<StandardMaterial>,
+        Option<&MaskPainter>,
+    )>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let Ok((prefab, mesh, material, painter)) = query.get(e) else {
+        return;
+    };
+    if let Some(painter) = painter {
+        commands.entity(painter.overlay).despawn();
+        images.remove(&painter.mask);
+        commands.entity(e).remove::<MaskPainter>();
+        return;
+    }
+
+    let size = image_size(material, &materials, &images).unwrap_or(UVec2::splat(DEFAULT_MASK_SIZE));
+    let mask = images.add(Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    ));
+
+    // shares the plane's mesh so it follows any resize
+    let overlay = commands
+        .spawn((
+            Name::new("Mask"),
+            Mesh3d(mesh.0.clone()),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color_texture: Some(mask.clone()),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_xyz(0.0, 0.0, 0.001),
+            NotShadowCaster,
+            Pickable::IGNORE,
+            ChildOf { parent: e },
+        ))
+        .id();
+
+    commands.entity(e).insert(MaskPainter {
+        mask,
+        overlay,
+        radius: size.max_element() as f32 * 0.03,
+        erase: false,
+        prompt: prefab.workflow.prompt().cloned().unwrap_or_default(),
+        grow: 6,
+        last: None,
+    });
+}
+
+#[derive(Event)]
+pub struct ClearMask;
+
+pub fn on_clear_mask(
+    trigger: Trigger<ClearMask>,
+    query: Query<&MaskPainter>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let Ok(painter) = query.get(trigger.target()) else {
+        return;
+    };
+    if let Some(data) = images
+        .get_mut(&painter.mask)
+        .and_then(|image| image.data.as_mut())
+    {
+        data.fill(0);
+    }
+}
+
+/// size of the image shown on the plane, None until loaded
+fn image_size(
+    material: &MeshMaterial3d<StandardMaterial>,
+    materials: &Assets<StandardMaterial>,
+    images: &Assets<Image>,
+) -> Option<UVec2> {
+    materials
+        .get(&material.0)
+        .and_then(|m| m.base_color_texture.as_ref())
+        .and_then(|texture| images.get(texture))
+        .map(|image| image.size())
+}
+
+/// mesh picking only gives the world position, the plane is flat so map it back to the image
+fn hit_uv(position: Option<Vec3>, plane: &PlaneSize, transform: &GlobalTransform) -> Option<Vec2> {
+    let local = transform.affine().inverse().transform_point3(position?);
+    let uv = Vec2::new(
+        local.x / (2.0 * plane.0.x) + 0.5,
+        0.5 - local.y / (2.0 * plane.0.y),
+    );
+    (uv.cmpge(Vec2::ZERO).all() && uv.cmple(Vec2::ONE).all()).then_some(uv)
+}
+
+pub fn on_mask_pressed(
+    trigger: Trigger<Pointer<Pressed>>,
+    mut query: Query<(&mut MaskPainter, &PlaneSize, &GlobalTransform)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if trigger.button != PointerButton::Primary {
+        return;
+    }
+    let Ok((mut painter, plane, transform)) = query.get_mut(trigger.target()) else {
+        return;
+    };
+    painter.last = None;
+    if let Some(uv) = hit_uv(trigger.hit.position, plane, transform) {
+        paint(&mut painter, &mut images, uv);
+    }
+}
+
+pub fn on_mask_move(
+    trigger: Trigger<Pointer<Move>>,
+    mut query: Query<(&mut MaskPainter, &PlaneSize, &GlobalTransform)>,
+    mut images: ResMut<Assets<Image>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+) {
+    let Ok((mut painter, plane, transform)) = query.get_mut(trigger.target()) else {
+        return;
+    };
+    if !mouse.pressed(MouseButton::Left) {
+        painter.last = None;
+        return;
+    }
+    match hit_uv(trigger.hit.position, plane, transform) {
+        Some(uv) => paint(&mut painter, &mut images, uv),
+        None => painter.last = None,
+    }
+}
+
+/// stamps the brush from the last position to `uv` so fast strokes dont leave gaps
+fn paint(painter: &mut MaskPainter, images: &mut Assets<Image>, uv: Vec2) {
+    let Some(image) = images.get_mut(&painter.mask) else {
+        return;
+    };
+    let size = image.size();
+    let pos = uv * size.as_vec2();
+    let from = painter.last.unwrap_or(pos);
+    let radius = painter.radius.max(1.0);
+    let color = if painter.erase { [0; 4] } else { MASK_COLOR };
+
+    let steps = (from.distance(pos) / (radius * 0.5)).ceil().max(1.0) as u32;
+    for step in 0..=steps {
+        let center = from.lerp(pos, step as f32 / steps as f32);
+        let min = (center - radius).max(Vec2::ZERO).as_uvec2();
+        let max = (center + radius).min(size.as_vec2() - 1.0).as_uvec2();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pixel = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                if pixel.distance(center) > radius {
+                    continue;
+                }
+                if let Some(bytes) = image.pixel_bytes_mut(UVec3::new(x, y, 0)) {
+                    bytes.copy_from_slice(&color);
+                }
+            }
+        }
+    }
+    painter.last = Some(pos);
+}
+
+/// the mask as ComfyUI's mask editor saves it, alpha 0 where painted,
+/// scaled to `size` if the image changed size since painting started
+fn mask_png(mask: &Image, size: UVec2) -> Option<Vec<u8>> {
+    let data = mask.data.as_ref()?;
+    let mask_size = mask.size();
+    let mut pixels = vec![0u8; (size.x * size.y * 4) as usize];
+    let mut painted = false;
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let mx = x * mask_size.x / size.x;
+            let my = y * mask_size.y / size.y;
+            let alpha = data[((my * mask_size.x + mx) * 4 + 3) as usize];
+            painted |= alpha > 0;
+            pixels[((y * size.x + x) * 4 + 3) as usize] = if alpha > 0 { 0 } else { 255 };
+        }
+    }
+    if !painted {
+        return None;
+    }
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, size.x, size.y);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels));
+    if let Err(err) = result {
+        error!("Failed to encode mask: {}", err);
+        return None;
+    }
+    Some(png_data)
+}
+
+/// regenerates the masked part of the prefab's image, the mask is kept so it can be rerun
+#[derive(Event)]
+pub struct Inpaint;
+
+pub fn on_inpaint(
+    trigger: Trigger<Inpaint>,
+    query: Query<(&Prefab, &MaskPainter, &MeshMaterial3d<StandardMaterial>)>,
+    images: Res<Assets<Image>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut queue: ResMut<GenerationQueue>,
+    mut rng: GlobalEntropy<WyRand>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let Ok((prefab, painter, material)) = query.get(e) else {
+        return;
+    };
+    if prefab.workflow.image().is_none() {
+        warn!("{} has no image to inpaint", prefab.name);
+        return;
+    }
+    let Some(mask) = images.get(&painter.mask) else {
+        return;
+    };
+    let size = image_size(material, &materials, &images).unwrap_or(mask.size());
+    let Some(mask) = mask_png(mask, size) else {
+        warn!(
+            "Nothing masked on {}, paint where to inpaint first",
+            prefab.name
+        );
+        return;
+    };
+
+    commands.entity(e).remove::<GenerationFailed>();
+    if !queue.is_running(e) {
+        commands.entity(e).insert(WorkflowProgress::new(1));
+    }
+    queue.push(GenerationJob {
+        entity: e,
+        name: prefab.name.clone(),
+        workflow: prefab.workflow.clone(),
+        stage: None,
+        priority: 0,
+        kind: JobKind::Inpaint(InpaintJob {
+            mask,
+            prompt: painter.prompt.clone(),
+            seed: rng.r#gen::<u64>(),
+            grow: painter.grow,
+        }),
+    });
+}
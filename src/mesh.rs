@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::WorkflowTemplate;
+
+/// stages of the model workflows, TextToModel also has the image as stage 0
+pub const SHAPE_STAGE: u8 = 1;
+/// paints the shape generated last, as a retexture from the prefab's image
+pub const TEXTURE_STAGE: u8 = 2;
+
+/// links from the background removal, multi-view has one per view
+const MASK_PARAMS: [&str; 4] = ["mesh_mask", "left_mask", "back_mask", "right_mask"];
+
+/// texture sizes offered, Hunyuan3D bakes to a square texture
+pub const TEXTURE_SIZES: [u32; 3] = [1024, 2048, 4096];
+
+/// Hunyuan3D shape and texture settings, defaults match ref_3d_gen.workflow.json
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeshSettings {
+    /// marching cubes resolution, higher keeps more detail
+    pub octree_resolution: u32,
+    pub steps: u32,
+    pub guidance: f32,
+    /// cut the subject out before generating, off for images already on a plain background
+    pub remove_background: bool,
+    /// off only generates the shape
+    pub texture: bool,
+    /// remove baked in lighting from the reference before texturing
+    pub delight: bool,
+    pub texture_size: u32,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            octree_resolution: 384,
+            steps: 50,
+            guidance: 5.5,
+            remove_background: true,
+            texture: true,
+            delight: true,
+            texture_size: 2048,
+        }
+    }
+}
+
+impl MeshSettings {
+    pub fn apply(&self, template: &WorkflowTemplate, workflow: &mut Value) {
+        template.set(workflow, "octree_resolution", self.octree_resolution);
+        template.set(workflow, "mesh_steps", self.steps);
+        template.set(workflow, "guidance", self.guidance);
+        template.set(workflow, "texture_size", self.texture_size);
+
+        // skipped nodes are left unlinked so ComfyUI never runs them
+        if !self.remove_background {
+            if let Some(image) = template.get(workflow, "mesh_image").cloned() {
+                for mask in MASK_PARAMS {
+                    template.unset(workflow, mask);
+                }
+                template.set(workflow, "delight_image", image);
+            }
+        }
+        if !self.delight {
+            if let Some(image) = template.get(workflow, "delight_image").cloned() {
+                template.set(workflow, "reference_image", image);
+            }
+        }
+    }
+
+    /// only run up to the shape
+    pub fn shape_only(&self, stage: Option<u8>) -> bool {
+        !self.texture || stage == Some(SHAPE_STAGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{WorkflowManifest, MULTIVIEW_TEMPLATE};
+
+    fn multiview() -> WorkflowTemplate {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/workflows");
+        let manifest = std::fs::read(dir.join(format!("{}.workflow.ron", MULTIVIEW_TEMPLATE)));
+        let manifest: WorkflowManifest = ron::de::from_bytes(&manifest.unwrap()).unwrap();
+        let json = std::fs::read(dir.join(&manifest.workflow)).unwrap();
+        let workflow = serde_json::from_slice(&json).unwrap();
+        WorkflowTemplate::new(MULTIVIEW_TEMPLATE.to_string(), manifest, workflow).unwrap()
+    }
+
+    #[test]
+    fn keeping_the_background_unlinks_every_view_mask() {
+        let template = multiview();
+        let mut workflow = template.instantiate();
+        let settings = MeshSettings {
+            remove_background: false,
+            ..default()
+        };
+        settings.apply(&template, &mut workflow);
+        for mask in MASK_PARAMS {
+            assert!(template.has_param(mask), "{} not in the manifest", mask);
+            assert_eq!(template.get(&workflow, mask), None);
+        }
+        assert_eq!(
+            template.get(&workflow, "delight_image"),
+            template.get(&workflow, "mesh_image")
+        );
+
+        // removing it leaves the workflow as saved
+        let mut workflow = template.instantiate();
+        MeshSettings::default().apply(&template, &mut workflow);
+        assert!(template.get(&workflow, "mesh_mask").is_some());
+    }
+}
@@ -0,0 +1,504 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use png::text_metadata::{ITXtChunk, TEXtChunk};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    Img2ImgSettings, LoraSettings, ModelSettings, SamplerSettings, Workflow, WorkflowTemplate,
+};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON: &[u8] = b"JSON";
+/// key under the glTF extras, and prefix of the png text keywords
+const METADATA_KEY: &str = "sly_ref";
+
+/// How a file was generated, embedded in every png and glb we write
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationMetadata {
+    pub prompt: Option<String>,
+    pub negative: Option<String>,
+    pub seed: Option<u64>,
+    pub checkpoint: Option<String>,
+    pub loras: Vec<String>,
+    /// the workflow exactly as queued
+    pub workflow: Option<Value>,
+}
+
+impl GenerationMetadata {
+    pub fn new(template: &WorkflowTemplate, workflow: &Value) -> Self {
+        let text = |param| {
+            template
+                .get(workflow, param)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+        // loras are chained in as extra nodes, so look for them instead of the param
+        let loras = workflow
+            .as_object()
+            .into_iter()
+            .flat_map(|nodes| nodes.values())
+            .filter_map(|node| node["inputs"]["lora_name"].as_str())
+            .map(|s| s.to_string())
+            .collect();
+        Self {
+            prompt: text("prompt"),
+            negative: text("negative"),
+            seed: template.get(workflow, "seed").and_then(|v| v.as_u64()),
+            checkpoint: text("checkpoint"),
+            loras,
+            workflow: Some(workflow.clone()),
+        }
+    }
+
+    /// adds the metadata to png or glb data, anything else is returned as is
+    pub fn embed(&self, path: &Path, data: &[u8]) -> Vec<u8> {
+        let result = match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => embed_png(data, self),
+            Some("glb") => embed_glb(data, self),
+            _ => return data.to_vec(),
+        };
+        result.unwrap_or_else(|err| {
+            warn!("Not embedding metadata in {:?}: {}", path, err);
+            data.to_vec()
+        })
+    }
+
+    /// reads what [`embed`](Self::embed) wrote, pngs straight from ComfyUI still have their workflow
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|err| err.to_string())?;
+        if data.starts_with(PNG_SIGNATURE) {
+            read_png(&data)
+        } else if data.starts_with(GLB_MAGIC) {
+            read_glb(&data)
+        } else {
+            Err("not a png or glb".to_string())
+        }
+    }
+
+    /// settings to make the image again, from our own chunks or the KSampler in the workflow,
+    /// None if the workflow has no sampler to take them from
+    pub fn text_to_image(&self, image: String, size: Option<UVec2>) -> Option<Workflow> {
+        let nodes = self.workflow.as_ref()?.as_object()?;
+        let inputs = nodes
+            .values()
+            .find(|n| {
+                matches!(
+                    n["class_type"].as_str(),
+                    Some("KSampler" | "KSamplerAdvanced")
+                )
+            })
+            .map(|n| &n["inputs"])?;
+        let text = |input: &str| {
+            resolve(
+                nodes,
+                &inputs[input],
+                &["text", "text_g", "value", "string"],
+                0,
+            )
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+        };
+        let number = |input: &str| resolve(nodes, &inputs[input], &["value"], 0);
+
+        let mut sampler = SamplerSettings::default();
+        if let Some(size) = size {
+            sampler.width = size.x;
+            sampler.height = size.y;
+        }
+        if let Some(steps) = number("steps").and_then(|v| v.as_u64()) {
+            sampler.steps = steps as u32;
+        }
+        if let Some(cfg) = number("cfg").and_then(|v| v.as_f64()) {
+            sampler.cfg = cfg as f32;
+        }
+        if let Some(name) = number("sampler_name").and_then(|v| v.as_str()) {
+            sampler.sampler = name.to_string();
+        }
+        if let Some(scheduler) = number("scheduler").and_then(|v| v.as_str()) {
+            sampler.scheduler = scheduler.to_string();
+        }
+        sampler.negative = self.negative.clone().or_else(|| text("negative"));
+
+        let models = ModelSettings {
+            checkpoint: self.checkpoint.clone().or_else(|| {
+                nodes
+                    .values()
+                    .find_map(|n| n["inputs"]["ckpt_name"].as_str())
+                    .map(|s| s.to_string())
+            }),
+            vae: None,
+            loras: Some(
+                nodes
+                    .values()
+                    .filter_map(|n| {
+                        let inputs = &n["inputs"];
+                        Some(LoraSettings {
+                            name: inputs["lora_name"].as_str()?.to_string(),
+                            strength_model: inputs["strength_model"].as_f64().unwrap_or(1.0) as f32,
+                            strength_clip: inputs["strength_clip"].as_f64().unwrap_or(1.0) as f32,
+                        })
+                    })
+                    .collect(),
+            ),
+        };
+
+        let seed = self.seed.or_else(|| {
+            number("seed")
+                .or_else(|| number("noise_seed"))
+                .and_then(|v| v.as_u64())
+        });
+        Some(Workflow::TextToImage {
+            seed: seed.unwrap_or_default(),
+            // keep the seed so the first generation makes the same image
+            seed_random: false,
+            prompt: self
+                .prompt
+                .clone()
+                .or_else(|| text("positive"))
+                .unwrap_or_default(),
+            image: Some(image),
+            models,
+            sampler,
+            img2img: Img2ImgSettings::default(),
+        })
+    }
+
+    fn text_chunks(&self) -> Vec<(String, String)> {
+        let mut chunks = Vec::new();
+        let mut add = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                chunks.push((format!("{}:{}", METADATA_KEY, key), value));
+            }
+        };
+        add("prompt", self.prompt.clone());
+        add("negative", self.negative.clone());
+        add("seed", self.seed.map(|s| s.to_string()));
+        add("checkpoint", self.checkpoint.clone());
+        add(
+            "loras",
+            (!self.loras.is_empty()).then(|| self.loras.join("\n")),
+        );
+        // same keyword ComfyUI uses, so the png can be dropped back into it
+        if let Some(workflow) = &self.workflow {
+            chunks.push(("prompt".to_string(), workflow.to_string()));
+        }
+        chunks
+    }
+}
+
+/// follows links back through other nodes until one has a literal under `keys`
+fn resolve<'a>(
+    nodes: &'a serde_json::Map<String, Value>,
+    value: &'a Value,
+    keys: &[&str],
+    depth: usize,
+) -> Option<&'a Value> {
+    // links are [node id, output index]
+    let Some(id) = value
+        .as_array()
+        .filter(|link| link.len() == 2)
+        .and_then(|link| link[0].as_str())
+    else {
+        return (!value.is_null()).then_some(value);
+    };
+    // workflows are acyclic, this only guards against broken ones
+    if depth > 16 {
+        return None;
+    }
+    let inputs = &nodes.get(id)?["inputs"];
+    keys.iter()
+        .find_map(|key| resolve(nodes, inputs.get(*key)?, keys, depth + 1))
+}
+
+/// width and height from the png header
+pub fn png_size(path: &Path) -> Option<UVec2> {
+    let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_ignore_text_chunk(true);
+    let reader = decoder.read_info().ok()?;
+    let info = reader.info();
+    Some(UVec2::new(info.width, info.height))
+}
+
+/// reads the whole png, text after the image data is only there once it is finished
+fn png_reader(data: &[u8]) -> Result<(png::Reader<&[u8]>, Vec<u8>), String> {
+    // a 4096 square 16 bit rgba is more than the default 64MiB
+    let limits = png::Limits { bytes: 1 << 30 };
+    let mut decoder = png::Decoder::new_with_limits(data, limits);
+    // keep the pixels exactly as stored so they can be written back unchanged
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    if reader.info().animation_control.is_some() {
+        return Err("animated png".to_string());
+    }
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader
+        .next_frame(&mut pixels)
+        .map_err(|err| err.to_string())?;
+    reader.finish().map_err(|err| err.to_string())?;
+    Ok((reader, pixels))
+}
+
+fn embed_png(data: &[u8], metadata: &GenerationMetadata) -> Result<Vec<u8>, String> {
+    let text = metadata.text_chunks();
+    let (reader, pixels) = png_reader(data)?;
+    let mut info = reader.info().clone();
+    // next_frame deinterlaced it
+    info.interlaced = false;
+    info.compression = png::Compression::Default;
+
+    // replace what ComfyUI wrote under the same keywords
+    let ours = |key: &String| text.iter().any(|(k, _)| k == key);
+    info.uncompressed_latin1_text.retain(|c| !ours(&c.keyword));
+    info.compressed_latin1_text.retain(|c| !ours(&c.keyword));
+    info.utf8_text.retain(|c| !ours(&c.keyword));
+    for (key, value) in text {
+        // tEXt is latin-1, anything else goes in an uncompressed iTXt
+        if value.chars().all(|c| (c as u32) < 256) {
+            info.uncompressed_latin1_text
+                .push(TEXtChunk::new(key, value));
+        } else {
+            info.utf8_text.push(ITXtChunk::new(key, value));
+        }
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    let mut writer = png::Encoder::with_info(&mut out, info)
+        .and_then(|encoder| encoder.write_header())
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(&pixels)
+        .and_then(|_| writer.finish())
+        .map_err(|err| err.to_string())?;
+    Ok(out)
+}
+
+fn read_png(data: &[u8]) -> Result<GenerationMetadata, String> {
+    let mut reader = png::Decoder::new(data)
+        .read_info()
+        .map_err(|err| err.to_string())?;
+    // skips the pixels but still reads the text after them
+    reader.finish().map_err(|err| err.to_string())?;
+    let info = reader.info();
+    let text = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|c| (&c.keyword, Ok(c.text.clone())))
+        .chain(
+            info.compressed_latin1_text
+                .iter()
+                .map(|c| (&c.keyword, c.get_text())),
+        )
+        .chain(info.utf8_text.iter().map(|c| (&c.keyword, c.get_text())));
+
+    let mut metadata = GenerationMetadata::default();
+    for (key, text) in text {
+        let Ok(text) = text else {
+            continue;
+        };
+        // ComfyUI's "prompt" is the workflow, ours are prefixed
+        if key == "prompt" {
+            metadata.workflow = serde_json::from_str(&text).ok();
+            continue;
+        }
+        let Some(key) = key
+            .strip_prefix(METADATA_KEY)
+            .and_then(|k| k.strip_prefix(':'))
+        else {
+            continue;
+        };
+        match key {
+            "prompt" => metadata.prompt = Some(text),
+            "negative" => metadata.negative = Some(text),
+            "seed" => metadata.seed = text.parse().ok(),
+            "checkpoint" => metadata.checkpoint = Some(text),
+            "loras" => metadata.loras = text.lines().map(|s| s.to_string()).collect(),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// json chunk and the rest of the file after it
+fn glb_json(data: &[u8]) -> Result<(Value, &[u8]), String> {
+    if data.len() < 20 || !data.starts_with(GLB_MAGIC) || &data[16..20] != GLB_JSON {
+        return Err("not a glb".to_string());
+    }
+    let len = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+    let json = data.get(20..20 + len).ok_or("truncated glb".to_string())?;
+    let json = serde_json::from_slice(json).map_err(|err| err.to_string())?;
+    Ok((json, &data[20 + len..]))
+}
+
+fn embed_glb(data: &[u8], metadata: &GenerationMetadata) -> Result<Vec<u8>, String> {
+    let (mut gltf, rest) = glb_json(data)?;
+    let value = serde_json::to_value(metadata).map_err(|err| err.to_string())?;
+    gltf["asset"]["extras"][METADATA_KEY] = value;
+
+    let mut json = serde_json::to_vec(&gltf).map_err(|err| err.to_string())?;
+    // chunks are 4 byte aligned, json pads with spaces
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let total = 12 + 8 + json.len() + rest.len();
+    let mut out = Vec::with_capacity(total);
+    out.extend_from_slice(&data[0..8]);
+    out.extend_from_slice(&(total as u32).to_le_bytes());
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(GLB_JSON);
+    out.extend_from_slice(&json);
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+fn read_glb(data: &[u8]) -> Result<GenerationMetadata, String> {
+    let (gltf, _) = glb_json(data)?;
+    match gltf["asset"]["extras"].get(METADATA_KEY) {
+        Some(value) => serde_json::from_value(value.clone()).map_err(|err| err.to_string()),
+        None => Ok(GenerationMetadata::default()),
+    }
+}
+
+/// File shown in the Metadata window
+#[derive(Resource, Debug, Default)]
+pub struct MetadataInspector {
+    pub open: bool,
+    /// relative to assets, or absolute
+    pub path: String,
+    pub metadata: Option<Result<GenerationMetadata, String>>,
+}
+
+impl MetadataInspector {
+    pub fn open(&mut self, path: &str) {
+        self.open = true;
+        self.path = path.to_string();
+        self.reload();
+    }
+
+    pub fn reload(&mut self) {
+        let path = Path::new(&self.path);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            Path::new("assets").join(path)
+        };
+        self.metadata = Some(GenerationMetadata::read(&path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> GenerationMetadata {
+        GenerationMetadata {
+            // not latin-1, goes in an iTXt
+            prompt: Some("a red car, 赤い車".to_string()),
+            negative: Some("blurry".to_string()),
+            seed: Some(1024),
+            checkpoint: Some("sdxl.safetensors".to_string()),
+            loras: vec!["a.safetensors".to_string(), "b.safetensors".to_string()],
+            workflow: Some(serde_json::json!({
+                "3": { "class_type": "KSampler", "inputs": { "seed": 1024 } }
+            })),
+        }
+    }
+
+    fn test_png(pixels: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, 2, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // what ComfyUI writes, replaced by ours
+        encoder
+            .add_text_chunk("prompt".to_string(), "{}".to_string())
+            .unwrap();
+        encoder
+            .add_text_chunk("parameters".to_string(), "kept".to_string())
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(pixels).unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    fn test_glb(bin: &[u8]) -> Vec<u8> {
+        let json = br#"{"asset":{"version":"2.0"}}"#;
+        let mut json = json.to_vec();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut out = Vec::new();
+        out.extend_from_slice(GLB_MAGIC);
+        out.extend_from_slice(&2u32.to_le_bytes());
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(GLB_JSON);
+        out.extend_from_slice(&json);
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"BIN\0");
+        out.extend_from_slice(bin);
+        out
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = (0..16).collect::<Vec<u8>>();
+        let data = metadata().embed(Path::new("car.png"), &test_png(&pixels));
+        assert_eq!(read_png(&data).unwrap(), metadata());
+
+        let mut reader = png::Decoder::new(&data[..]).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, pixels);
+        reader.finish().unwrap();
+        let info = reader.info();
+        let keys = info
+            .uncompressed_latin1_text
+            .iter()
+            .map(|c| c.keyword.as_str())
+            .chain(info.utf8_text.iter().map(|c| c.keyword.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys.iter().filter(|k| **k == "prompt").count(), 1);
+        assert!(keys.contains(&"parameters"));
+    }
+
+    #[test]
+    fn png_embed_twice_replaces() {
+        let data = test_png(&[0; 16]);
+        let first = metadata().embed(Path::new("car.png"), &data);
+        let other = GenerationMetadata {
+            seed: Some(7),
+            ..metadata()
+        };
+        let second = other.embed(Path::new("car.png"), &first);
+        assert_eq!(read_png(&second).unwrap(), other);
+    }
+
+    #[test]
+    fn glb_round_trip() {
+        let bin = [1, 2, 3, 4];
+        let data = metadata().embed(Path::new("car.glb"), &test_glb(&bin));
+        assert_eq!(read_glb(&data).unwrap(), metadata());
+
+        let total = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+        assert_eq!(total, data.len());
+        let json_len = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        assert_eq!(json_len % 4, 0);
+        assert!(data.ends_with(&bin));
+        let (gltf, _) = glb_json(&data).unwrap();
+        assert_eq!(gltf["asset"]["version"], "2.0");
+    }
+
+    #[test]
+    fn unknown_data_is_untouched() {
+        let data = b"not an image".to_vec();
+        assert_eq!(metadata().embed(Path::new("car.png"), &data), data);
+        assert_eq!(metadata().embed(Path::new("car.obj"), &data), data);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TaskContext;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{ComfyClient, WorkflowTemplate};
+
+/// model folders listed for the pickers
+pub const CHECKPOINTS: &str = "checkpoints";
+pub const LORAS: &str = "loras";
+pub const VAES: &str = "vae";
+
+/// File names in ComfyUI's model folders, by folder
+#[derive(Resource, Default, Debug)]
+pub struct ComfyModels(pub HashMap<String, Vec<String>>);
+
+impl ComfyModels {
+    pub fn get(&self, folder: &str) -> &[String] {
+        self.0.get(folder).map(|v| v.as_slice()).unwrap_or_default()
+    }
+}
+
+/// Fetches the model lists, called on every (re)connect since models can be added meanwhile
+pub async fn fetch_models(comfy: &ComfyClient, ctx: &mut TaskContext) {
+    let mut models = HashMap::new();
+    for folder in [CHECKPOINTS, LORAS, VAES] {
+        match comfy.list_models(folder).await {
+            Ok(list) => {
+                models.insert(folder.to_string(), list);
+            }
+            Err(err) => warn!("Failed to list {} from ComfyUI: {}", folder, err),
+        }
+    }
+    ctx.run_on_main_thread(move |ctx| {
+        ctx.world.insert_resource(ComfyModels(models));
+    })
+    .await;
+}
+
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct LoraSettings {
+    pub name: String,
+    pub strength_model: f32,
+    pub strength_clip: f32,
+}
+
+impl Default for LoraSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            strength_model: 1.0,
+            strength_clip: 1.0,
+        }
+    }
+}
+
+/// Checkpoint, VAE and LoRAs for a generation, None keeps what the workflow was saved with
+#[derive(Debug, Default, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct ModelSettings {
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    #[serde(default)]
+    pub vae: Option<String>,
+    /// applied in order, an empty list removes the workflow's lora
+    #[serde(default)]
+    pub loras: Option<Vec<LoraSettings>>,
+}
+
+impl ModelSettings {
+    /// sets the "checkpoint" param, adds a VAELoader feeding the "vae" param
+    /// and replaces the "lora" param's node with a chain of LoraLoaders
+    pub fn apply(&self, template: &WorkflowTemplate, workflow: &mut Value) {
+        if let Some(checkpoint) = &self.checkpoint {
+            template.set(workflow, "checkpoint", checkpoint);
+        }
+        if let Some(vae) = &self.vae {
+            if template.has_param("vae") {
+                let id = "sly_vae".to_string();
+                workflow[id.as_str()] = json!({
+                    "class_type": "VAELoader",
+                    "inputs": { "vae_name": vae },
+                    "_meta": { "title": "Load VAE" },
+                });
+                template.set(workflow, "vae", json!([id, 0]));
+            } else {
+                warn!("{} has no vae param, using its own", template.name);
+            }
+        }
+        if let Some(loras) = &self.loras {
+            match template.param_node("lora") {
+                Some(node) => chain_loras(workflow, node, loras),
+                None => warn!("{} has no lora param, ignoring loras", template.name),
+            }
+        }
+    }
+
+    /// what the "lora" param's node is set to in the workflow
+    pub fn template_loras(template: &WorkflowTemplate) -> Vec<LoraSettings> {
+        let Some(node) = template.param_node("lora") else {
+            return Vec::new();
+        };
+        let inputs = &template.workflow[node]["inputs"];
+        vec![LoraSettings {
+            name: inputs["lora_name"].as_str().unwrap_or_default().to_string(),
+            strength_model: inputs["strength_model"].as_f64().unwrap_or(1.0) as f32,
+            strength_clip: inputs["strength_clip"].as_f64().unwrap_or(1.0) as f32,
+        }]
+    }
+}
+
+/// Rebuilds `node` as a chain of LoraLoaders, or bypasses it if there are none
+fn chain_loras(workflow: &mut Value, node: &str, loras: &[LoraSettings]) {
+    let inputs = &workflow[node]["inputs"];
+    let mut model = inputs["model"].clone();
+    let mut clip = inputs["clip"].clone();
+
+    let mut chain = Vec::new();
+    for (i, lora) in loras.iter().enumerate() {
+        let id = if i == 0 {
+            node.to_string()
+        } else {
+            format!("{}_lora_{}", node, i)
+        };
+        workflow[id.as_str()] = json!({
+            "class_type": "LoraLoader",
+            "inputs": {
+                "lora_name": lora.name,
+                "strength_model": lora.strength_model,
+                "strength_clip": lora.strength_clip,
+                "model": model,
+                "clip": clip,
+            },
+            "_meta": { "title": format!("Load LoRA {}", i + 1) },
+        });
+        model = json!([id, 0]);
+        clip = json!([id, 1]);
+        chain.push(id);
+    }
+    if loras.is_empty() {
+        if let Some(nodes) = workflow.as_object_mut() {
+            nodes.remove(node);
+        }
+    }
+
+    // anything that used the original node now uses the end of the chain
+    let Some(nodes) = workflow.as_object_mut() else {
+        return;
+    };
+    for (id, n) in nodes.iter_mut() {
+        if chain.contains(id) {
+            continue;
+        }
+        let Some(inputs) = n["inputs"].as_object_mut() else {
+            continue;
+        };
+        for value in inputs.values_mut() {
+            if *value == json!([node, 0]) {
+                *value = model.clone();
+            } else if *value == json!([node, 1]) {
+                *value = clip.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow() -> Value {
+        json!({
+            "4": { "class_type": "CheckpointLoaderSimple", "inputs": {} },
+            "10": {
+                "class_type": "LoraLoader",
+                "inputs": {
+                    "lora_name": "old.safetensors",
+                    "strength_model": 1.0,
+                    "strength_clip": 1.0,
+                    "model": ["4", 0],
+                    "clip": ["4", 1],
+                },
+            },
+            "3": { "class_type": "KSampler", "inputs": { "model": ["10", 0], "seed": 1 } },
+            "6": { "class_type": "CLIPTextEncode", "inputs": { "clip": ["10", 1] } },
+        })
+    }
+
+    fn lora(name: &str) -> LoraSettings {
+        LoraSettings {
+            name: name.to_string(),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn chains_loras_in_order() {
+        let mut workflow = workflow();
+        chain_loras(&mut workflow, "10", &[lora("a"), lora("b")]);
+        assert_eq!(workflow["10"]["inputs"]["lora_name"], "a");
+        assert_eq!(workflow["10"]["inputs"]["model"], json!(["4", 0]));
+        assert_eq!(workflow["10_lora_1"]["inputs"]["lora_name"], "b");
+        assert_eq!(workflow["10_lora_1"]["inputs"]["model"], json!(["10", 0]));
+        assert_eq!(workflow["10_lora_1"]["inputs"]["clip"], json!(["10", 1]));
+        assert_eq!(workflow["3"]["inputs"]["model"], json!(["10_lora_1", 0]));
+        assert_eq!(workflow["6"]["inputs"]["clip"], json!(["10_lora_1", 1]));
+    }
+
+    #[test]
+    fn no_loras_bypasses_the_node() {
+        let mut workflow = workflow();
+        chain_loras(&mut workflow, "10", &[]);
+        assert!(workflow.get("10").is_none());
+        assert_eq!(workflow["3"]["inputs"]["model"], json!(["4", 0]));
+        assert_eq!(workflow["6"]["inputs"]["clip"], json!(["4", 1]));
+        // other inputs are left alone
+        assert_eq!(workflow["3"]["inputs"]["seed"], 1);
+    }
+}
@@ -1,8 +1,19 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
-use crate::{comfy, Selected, WorkflowProgress};
+use crate::{
+    on_add_version, on_clear_mask, on_discard_variants, on_inpaint, on_keep_variants, on_mask_move,
+    on_mask_pressed, on_promote_variant, on_retexture, on_revert, on_set_variants, on_toggle_mask,
+    prune_workflow, record_original, record_version, remove_history, remove_variants,
+    rename_variants, variant_path, AddVersion, ComfyClient, ComfyError, GenerationJob,
+    GenerationMetadata, GenerationQueue, Img2ImgSettings, InpaintJob, JobKind, MaskPainter,
+    MeshSettings, ModelSettings, OutputKind, ParamValue, ProgressChannel, ProgressSender,
+    RetextureJob, SamplerSettings, Selected, SetVariants, Version, WorkflowProgress,
+    WorkflowTemplate, WorkflowTemplates, IMAGE_TEMPLATE, IMG2IMG_TEMPLATE, INPAINT_TEMPLATE,
+    MODEL_TEMPLATE, MULTIVIEW_TEMPLATE, RETEXTURE_TEMPLATE, TEXTURE_STAGE,
+};
 use bevy::ecs::component::HookContext;
 use bevy::ecs::world::DeferredWorld;
 use bevy::pbr::NotShadowCaster;
@@ -12,10 +23,11 @@ use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
 use bevy_prng::WyRand;
 use bevy_rand::global::GlobalEntropy;
 use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use tokio::task::AbortHandle;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use strum::EnumIter;
+use serde_json::Value;
+use strum::{EnumIter, IntoEnumIterator};
 
 // TODO: remove need for this
 // hack to delay upating assets so reload works
@@ -32,6 +44,19 @@ const FILE_DELAY: f32 = 1.0;
 pub struct Prefab {
     pub name: String,
     pub workflow: Workflow,
+    /// other images from the last batch, picked from the strip under the image
+    #[serde(default)]
+    pub variants: Vec<String>,
+    /// every image and model generated, oldest first
+    #[serde(default)]
+    pub history: Vec<Version>,
+}
+
+impl Prefab {
+    /// an output of this kind is in the history, so the current file can be overwritten
+    pub fn has_version(&self, output: OutputKind) -> bool {
+        self.history.iter().any(|v| v.output == output)
+    }
 }
 
 #[derive(Component, EnumIter, PartialEq, Reflect, Debug, Clone, Serialize, Deserialize)]
@@ -40,20 +65,106 @@ pub enum Workflow {
     StaticImage {
         image: Option<String>,
     },
+    /// an existing model, ex an imported glb or one from a kit, nothing is generated
+    StaticModel {
+        model: Option<String>,
+    },
     TextToImage {
-        seed: u32,
+        seed: u64,
         seed_random: bool,
         prompt: String,
         image: Option<String>,
+        #[serde(default)]
+        models: ModelSettings,
+        #[serde(default)]
+        sampler: SamplerSettings,
+        #[serde(default)]
+        img2img: Img2ImgSettings,
     },
     TextToModel {
-        seed: u32,
+        seed: u64,
         seed_random: bool,
         prompt: String,
         num_faces: u32,
         image: Option<String>,
         model: Option<String>,
+        /// used by the image stage
+        #[serde(default)]
+        models: ModelSettings,
+        #[serde(default)]
+        sampler: SamplerSettings,
+        #[serde(default)]
+        mesh: MeshSettings,
+    },
+    /// model from an existing image, ex one pasted in, skips generating the image
+    ImageToModel {
+        seed: u64,
+        seed_random: bool,
+        num_faces: u32,
+        image: Option<String>,
+        model: Option<String>,
+        #[serde(default)]
+        mesh: MeshSettings,
+    },
+    /// model from up to four views of the same subject, the front is the prefab's
+    /// own image unless another is linked, the rest are images of other prefabs
+    MultiViewToModel {
+        seed: u64,
+        seed_random: bool,
+        num_faces: u32,
+        views: ModelViews,
+        image: Option<String>,
+        model: Option<String>,
+        #[serde(default)]
+        mesh: MeshSettings,
     },
+    /// any template in assets/workflows, params are declared by its manifest
+    Custom {
+        template: String,
+        params: BTreeMap<String, ParamValue>,
+        outputs: OutputSlots,
+    },
+}
+
+/// Where a custom workflow's outputs end up
+#[derive(Debug, Default, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct OutputSlots {
+    pub image: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Board images linked as views of a multi-view model, by asset path
+#[derive(Debug, Default, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct ModelViews {
+    pub front: Option<String>,
+    pub left: Option<String>,
+    pub back: Option<String>,
+    pub right: Option<String>,
+}
+
+impl ModelViews {
+    /// template param of each view, in the order they are shown
+    pub const NAMES: [&'static str; 4] = ["front", "left", "back", "right"];
+
+    pub fn get_mut(&mut self, view: &str) -> Option<&mut Option<String>> {
+        match view {
+            "front" => Some(&mut self.front),
+            "left" => Some(&mut self.left),
+            "back" => Some(&mut self.back),
+            "right" => Some(&mut self.right),
+            _ => None,
+        }
+    }
+
+    /// (view, image) of every view, front falls back to the prefab's image
+    pub fn images(&self, image: Option<&String>) -> [(&'static str, Option<String>); 4] {
+        [
+            ("front", self.front.as_ref().or(image).cloned()),
+            ("left", self.left.clone()),
+            ("back", self.back.clone()),
+            ("right", self.right.clone()),
+        ]
+    }
 }
 
 impl Default for Workflow {
@@ -62,12 +173,265 @@ impl Default for Workflow {
     }
 }
 
+impl Workflow {
+    pub fn image(&self) -> Option<&String> {
+        match self {
+            Workflow::StaticImage { image }
+            | Workflow::TextToImage { image, .. }
+            | Workflow::TextToModel { image, .. }
+            | Workflow::ImageToModel { image, .. }
+            | Workflow::MultiViewToModel { image, .. } => image.as_ref(),
+            Workflow::Custom { outputs, .. } => outputs.image.as_ref(),
+            Workflow::StaticModel { .. } => None,
+        }
+    }
+
+    pub fn model(&self) -> Option<&String> {
+        match self {
+            Workflow::StaticModel { model }
+            | Workflow::TextToModel { model, .. }
+            | Workflow::ImageToModel { model, .. }
+            | Workflow::MultiViewToModel { model, .. } => model.as_ref(),
+            Workflow::Custom { outputs, .. } => outputs.model.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// ignored by workflows without an image
+    pub fn set_image(&mut self, path: String) {
+        match self {
+            Workflow::StaticImage { image }
+            | Workflow::TextToImage { image, .. }
+            | Workflow::TextToModel { image, .. }
+            | Workflow::ImageToModel { image, .. }
+            | Workflow::MultiViewToModel { image, .. } => *image = Some(path),
+            Workflow::Custom { outputs, .. } => outputs.image = Some(path),
+            Workflow::StaticModel { .. } => {}
+        }
+    }
+
+    /// ignored by workflows without a model
+    pub fn set_model(&mut self, path: String) {
+        match self {
+            Workflow::StaticModel { model }
+            | Workflow::TextToModel { model, .. }
+            | Workflow::ImageToModel { model, .. }
+            | Workflow::MultiViewToModel { model, .. } => *model = Some(path),
+            Workflow::Custom { outputs, .. } => outputs.model = Some(path),
+            _ => {}
+        }
+    }
+
+    /// image and model paths, for copying, renaming and deleting
+    pub fn files_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        let (image, model) = match self {
+            Workflow::StaticImage { image } | Workflow::TextToImage { image, .. } => {
+                (Some(image), None)
+            }
+            Workflow::StaticModel { model } => (None, Some(model)),
+            Workflow::TextToModel { image, model, .. }
+            | Workflow::ImageToModel { image, model, .. }
+            | Workflow::MultiViewToModel { image, model, .. } => (Some(image), Some(model)),
+            Workflow::Custom { outputs, .. } => {
+                (Some(&mut outputs.image), Some(&mut outputs.model))
+            }
+        };
+        [image, model]
+            .into_iter()
+            .flatten()
+            .filter_map(|file| file.as_mut())
+    }
+
+    pub fn prompt(&self) -> Option<&String> {
+        match self {
+            Workflow::TextToImage { prompt, .. } | Workflow::TextToModel { prompt, .. } => {
+                Some(prompt)
+            }
+            Workflow::Custom { params, .. } => match params.get("prompt") {
+                Some(ParamValue::String(prompt)) => Some(prompt),
+                _ => None,
+            },
+            Workflow::StaticImage { .. }
+            | Workflow::StaticModel { .. }
+            | Workflow::ImageToModel { .. }
+            | Workflow::MultiViewToModel { .. } => None,
+        }
+    }
+
+    /// seed and if it is randomized
+    pub fn seed(&self) -> Option<(u64, bool)> {
+        match self {
+            Workflow::TextToImage {
+                seed, seed_random, ..
+            }
+            | Workflow::TextToModel {
+                seed, seed_random, ..
+            }
+            | Workflow::ImageToModel {
+                seed, seed_random, ..
+            }
+            | Workflow::MultiViewToModel {
+                seed, seed_random, ..
+            } => Some((*seed, *seed_random)),
+            Workflow::Custom { params, .. } => match params.get("seed") {
+                Some(ParamValue::Seed { seed, random }) => Some((*seed, *random)),
+                _ => None,
+            },
+            Workflow::StaticImage { .. } | Workflow::StaticModel { .. } => None,
+        }
+    }
+
+    /// face count of the generated model
+    pub fn num_faces(&self) -> Option<u32> {
+        match self {
+            Workflow::TextToModel { num_faces, .. }
+            | Workflow::ImageToModel { num_faces, .. }
+            | Workflow::MultiViewToModel { num_faces, .. } => Some(*num_faces),
+            _ => None,
+        }
+    }
+
+    pub fn mesh(&self) -> Option<&MeshSettings> {
+        match self {
+            Workflow::TextToModel { mesh, .. }
+            | Workflow::ImageToModel { mesh, .. }
+            | Workflow::MultiViewToModel { mesh, .. } => Some(mesh),
+            _ => None,
+        }
+    }
+
+    pub fn models(&self) -> Option<&ModelSettings> {
+        match self {
+            Workflow::TextToImage { models, .. } | Workflow::TextToModel { models, .. } => {
+                Some(models)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn sampler(&self) -> Option<&SamplerSettings> {
+        match self {
+            Workflow::TextToImage { sampler, .. } | Workflow::TextToModel { sampler, .. } => {
+                Some(sampler)
+            }
+            _ => None,
+        }
+    }
+
+    /// width / height of the image it generates, if known
+    pub fn aspect(&self) -> Option<f32> {
+        match self {
+            // keeps the size of the image it starts from
+            Workflow::TextToImage { img2img, .. } if img2img.enabled => None,
+            _ => self.sampler().map(|s| s.aspect()),
+        }
+    }
+
+    /// names of the templates this workflow queues
+    pub fn templates(&self) -> Vec<&str> {
+        match self {
+            Workflow::StaticImage { .. } | Workflow::StaticModel { .. } => vec![],
+            Workflow::TextToImage { img2img, .. } if img2img.enabled => vec![IMG2IMG_TEMPLATE],
+            Workflow::TextToImage { .. } => vec![IMAGE_TEMPLATE],
+            Workflow::TextToModel { .. } => vec![IMAGE_TEMPLATE, MODEL_TEMPLATE],
+            Workflow::ImageToModel { .. } => vec![MODEL_TEMPLATE],
+            Workflow::MultiViewToModel { .. } => vec![MULTIVIEW_TEMPLATE],
+            Workflow::Custom { template, .. } => vec![template.as_str()],
+        }
+    }
+
+    /// same variant, and same template for custom workflows
+    pub fn same_kind(&self, other: &Workflow) -> bool {
+        match (self, other) {
+            (Workflow::Custom { template: a, .. }, Workflow::Custom { template: b, .. }) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+
+    /// `to`'s variant, keeping whatever settings both have
+    pub fn convert(&self, to: &Workflow) -> Workflow {
+        let image = self.image().cloned();
+        let model = self.model().cloned();
+        let prompt = self.prompt().cloned().unwrap_or_default();
+        let (seed, seed_random) = self.seed().unwrap_or_default();
+        let models = self.models().cloned().unwrap_or_default();
+        let sampler = self.sampler().cloned().unwrap_or_default();
+        let num_faces = self.num_faces().unwrap_or(50000);
+        let mesh = self.mesh().cloned().unwrap_or_default();
+        match to {
+            Workflow::StaticImage { .. } => Workflow::StaticImage { image },
+            Workflow::StaticModel { .. } => Workflow::StaticModel { model },
+            Workflow::TextToImage { .. } => Workflow::TextToImage {
+                seed,
+                seed_random,
+                prompt,
+                image,
+                models,
+                sampler,
+                img2img: default(),
+            },
+            Workflow::TextToModel { .. } => Workflow::TextToModel {
+                seed,
+                seed_random,
+                prompt,
+                num_faces,
+                image,
+                model,
+                models,
+                sampler,
+                mesh,
+            },
+            Workflow::ImageToModel { .. } => Workflow::ImageToModel {
+                seed,
+                seed_random,
+                num_faces,
+                image,
+                model,
+                mesh,
+            },
+            Workflow::MultiViewToModel { .. } => Workflow::MultiViewToModel {
+                seed,
+                seed_random,
+                num_faces,
+                views: match self {
+                    Workflow::MultiViewToModel { views, .. } => views.clone(),
+                    _ => default(),
+                },
+                image,
+                model,
+                mesh,
+            },
+            Workflow::Custom {
+                template, params, ..
+            } => {
+                let mut params = params.clone();
+                if let Some(ParamValue::String(p)) = params.get_mut("prompt") {
+                    *p = prompt;
+                }
+                if let Some(ParamValue::Seed { seed: s, random }) = params.get_mut("seed") {
+                    *s = seed;
+                    *random = seed_random;
+                }
+                Workflow::Custom {
+                    template: template.clone(),
+                    params,
+                    outputs: OutputSlots { image, model },
+                }
+            }
+        }
+    }
+}
+
 impl Display for Workflow {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Workflow::StaticImage { .. } => write!(f, "Static Image"),
+            Workflow::StaticModel { .. } => write!(f, "Static Model"),
             Workflow::TextToImage { .. } => write!(f, "Text To Image"),
             Workflow::TextToModel { .. } => write!(f, "Text To Model"),
+            Workflow::ImageToModel { .. } => write!(f, "Image To Model"),
+            Workflow::MultiViewToModel { .. } => write!(f, "Multi-View To Model"),
+            Workflow::Custom { template, .. } => write!(f, "{}", template),
         }
     }
 }
@@ -79,33 +443,42 @@ pub fn on_add_prefab(mut world: DeferredWorld<'_>, HookContext { entity, .. }: H
         .observe(on_drag)
         .observe(on_select)
         .observe(on_duplicate)
+        .observe(on_variation)
         .observe(on_delete)
         .observe(on_rename)
         .observe(on_generate)
+        .observe(on_cancel)
+        .observe(on_retry)
         .observe(on_refresh_image)
-        .observe(on_refresh_model);
+        .observe(on_refresh_model)
+        .observe(on_toggle_mask)
+        .observe(on_clear_mask)
+        .observe(on_mask_pressed)
+        .observe(on_mask_move)
+        .observe(on_inpaint)
+        .observe(on_retexture)
+        .observe(on_set_variants)
+        .observe(on_promote_variant)
+        .observe(on_keep_variants)
+        .observe(on_discard_variants)
+        .observe(on_add_version)
+        .observe(on_revert);
 
     let prefab = world.entity(entity).get::<Prefab>().unwrap();
 
-    let (image, model) = match &prefab.workflow {
-        Workflow::TextToImage { image, .. } => (image.clone(), None),
-        Workflow::TextToModel { image, model, .. } => (image.clone(), model.clone()),
-        Workflow::StaticImage { image } => (image.clone(), None),
-    };
+    let image = prefab.workflow.image().cloned();
+    let model = prefab.workflow.model().cloned();
 
     let asset_server = world.get_resource::<AssetServer>().unwrap();
     let image_handle: Handle<Image> = match image {
         Some(img) => asset_server.load(img),
         None => Handle::<Image>::default(),
     };
-    let scene = if let Some(model) = model {
-        Some(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model)))
-    } else {
-        None
-    };
+    let scene = model.map(|model| load_model(asset_server, model));
 
     let mut meshes = world.get_resource_mut::<Assets<Mesh>>().unwrap();
-    let mesh = meshes.add(Plane3d::new(Vec3::Z, Vec2::splat(1.0)));
+    let size = plane_size(prefab.workflow.aspect().unwrap_or(1.0));
+    let mesh = meshes.add(Plane3d::new(Vec3::Z, size));
 
     let mut materials = world
         .get_resource_mut::<Assets<StandardMaterial>>()
@@ -120,7 +493,12 @@ pub fn on_add_prefab(mut world: DeferredWorld<'_>, HookContext { entity, .. }: H
     let e = world
         .commands()
         .entity(entity)
-        .insert((NotShadowCaster, Mesh3d(mesh), MeshMaterial3d(mat)))
+        .insert((
+            NotShadowCaster,
+            Mesh3d(mesh),
+            MeshMaterial3d(mat),
+            PlaneSize(size),
+        ))
         .id();
 
     let model_entity = world
@@ -140,10 +518,48 @@ pub fn on_add_prefab(mut world: DeferredWorld<'_>, HookContext { entity, .. }: H
     }
 }
 
-// move relative to camera
+/// scene of a model file, gltf and glb load their first scene, obj loads whole
+pub fn load_model(asset_server: &AssetServer, model: String) -> Handle<Scene> {
+    if model.ends_with(".obj") {
+        asset_server.load(model)
+    } else {
+        asset_server.load(GltfAssetLabel::Scene(0).from_asset(model))
+    }
+}
+
+/// half size of the image plane, longest side 1
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PlaneSize(pub Vec2);
+
+fn plane_size(aspect: f32) -> Vec2 {
+    if aspect >= 1.0 {
+        Vec2::new(1.0, 1.0 / aspect)
+    } else {
+        Vec2::new(aspect, 1.0)
+    }
+}
+
+/// reshapes the plane when the image size changes
+pub fn update_plane_size(
+    mut query: Query<(&Prefab, &Mesh3d, &mut PlaneSize), Changed<Prefab>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (prefab, mesh, mut plane) in query.iter_mut() {
+        let Some(aspect) = prefab.workflow.aspect() else {
+            continue;
+        };
+        let size = plane_size(aspect);
+        if plane.0 != size {
+            plane.0 = size;
+            meshes.insert(&mesh.0, Plane3d::new(Vec3::Z, size).into());
+        }
+    }
+}
+
+// move relative to camera, unless painting a mask
 fn on_drag(
     drag: Trigger<Pointer<Drag>>,
-    mut transforms: Query<&mut Transform, (Without<Camera>, With<Prefab>)>,
+    mut transforms: Query<&mut Transform, (Without<Camera>, With<Prefab>, Without<MaskPainter>)>,
     camera_transforms: Single<&mut Transform, With<Camera>>,
     time: Res<Time>,
 ) {
@@ -201,44 +617,139 @@ fn on_duplicate(
         .map(|(p, _)| p.name.clone())
         .collect::<Vec<_>>();
 
-    let new_name = create_unique_name(&prefab.name, names);
+    let new_prefab = copy_prefab(prefab, names);
 
-    let mut new_prefab = prefab.clone();
-    new_prefab.name = new_name.clone();
+    commands.spawn((
+        Transform::from_translation(trans.translation + Vec3::new(2.0, 0., 0.1)), // offset z so no z fighting
+        Name::new(new_prefab.name.clone()),
+        new_prefab,
+    ));
+}
 
-    match &mut new_prefab.workflow {
-        Workflow::StaticImage { image } => {
-            if let Some(img) = image {
-                *img = copy_asset(&img, &new_prefab.name);
-            }
-        }
-        Workflow::TextToImage { image, .. } => {
-            if let Some(img) = image {
-                *img = copy_asset(&img, &new_prefab.name);
-            }
+/// clone with a unique name and its own copies of the files
+fn copy_prefab(prefab: &Prefab, names: Vec<String>) -> Prefab {
+    let mut new_prefab = prefab.clone();
+    new_prefab.name = create_unique_name(&prefab.name, names);
+    // the variants and history stay with the original
+    new_prefab.variants.clear();
+    new_prefab.history.clear();
+
+    let name = new_prefab.name.clone();
+    for file in new_prefab.workflow.files_mut() {
+        match copy_asset(file, &name) {
+            Ok(new_file) => *file = new_file,
+            Err(err) => error!("Failed to copy {} for {}: {}", file, name, err),
         }
-        Workflow::TextToModel { image, model, .. } => {
-            if let Some(img) = image {
-                *img = copy_asset(&img, &new_prefab.name);
-            }
-            if let Some(m) = model {
-                *m = copy_asset(&m, &new_prefab.name);
-            }
+    }
+    new_prefab
+}
+
+// copies the prefab and refines the copy starting from its image
+#[derive(Event)]
+pub struct Variation;
+
+fn on_variation(
+    trigger: Trigger<Variation>,
+    mut commands: Commands,
+    query: Query<(&Prefab, &Transform)>,
+) {
+    let entity = trigger.target();
+    let (prefab, trans) = query.get(entity).unwrap();
+    if prefab.workflow.image().is_none() {
+        warn!("{} has no image to make a variation of", prefab.name);
+        return;
+    }
+
+    let mut source = prefab.clone();
+    if !matches!(source.workflow, Workflow::TextToImage { .. }) {
+        let text_to_image = Workflow::iter()
+            .find(|w| matches!(w, Workflow::TextToImage { .. }))
+            .unwrap();
+        source.workflow = source.workflow.convert(&text_to_image);
+    }
+    if let Workflow::TextToImage { img2img, .. } = &mut source.workflow {
+        img2img.enabled = true;
+    }
+
+    let names = query
+        .iter()
+        .map(|(p, _)| p.name.clone())
+        .collect::<Vec<_>>();
+    let new_prefab = copy_prefab(&source, names);
+
+    let new_entity = commands
+        .spawn((
+            Transform::from_translation(trans.translation + Vec3::new(2.0, 0., 0.1)),
+            Name::new(new_prefab.name.clone()),
+            new_prefab,
+        ))
+        .id();
+    commands.trigger_targets(Generate(None), new_entity);
+}
+
+/// imported gltf and obj files sit in a folder of their own next to their buffers,
+/// materials and textures, the folder is copied, renamed and deleted with them
+fn model_folder(file: &Path) -> Option<&Path> {
+    let folder = file.parent()?;
+    let ext = file.extension()?;
+    ((ext == "gltf" || ext == "obj") && folder != Path::new("ref")).then_some(folder)
+}
+
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dst.join(entry.file_name()))?;
+        } else {
+            std::fs::copy(entry.path(), dst.join(entry.file_name()))?;
         }
     }
+    Ok(())
+}
+
+/// copies or renames a model's folder to ref/<name>, then names the model inside after it
+fn move_model_folder(
+    file: &Path,
+    folder: &Path,
+    name: &String,
+    copy: bool,
+) -> std::io::Result<String> {
+    let asset_path = Path::new("./assets/");
+    let new_folder = Path::new("ref").join(name);
+    let ext = file.extension().unwrap_or_default().to_string_lossy();
+    let new_file = new_folder.join(format!("{}.{}", name, ext));
+
+    // never merge into a folder that is already there, it belongs to something else
+    if asset_path.join(&new_folder).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", new_folder),
+        ));
+    }
+    if copy {
+        copy_dir(&asset_path.join(folder), &asset_path.join(&new_folder))?;
+    } else {
+        std::fs::rename(asset_path.join(folder), asset_path.join(&new_folder))?;
+    }
 
-    commands.spawn((
-        Transform::from_translation(trans.translation + Vec3::new(2.0, 0., 0.1)), // offset z so no z fighting
-        Name::new(new_name.clone()),
-        new_prefab,
-    ));
+    // the folder moved, so keep pointing at the model inside if it cant be renamed
+    let moved = new_folder.join(file.file_name().unwrap_or_default());
+    if let Err(err) = std::fs::rename(asset_path.join(&moved), asset_path.join(&new_file)) {
+        error!("Failed to rename {:?} to {:?}: {}", moved, new_file, err);
+        return Ok(moved.to_string_lossy().to_string());
+    }
+    Ok(new_file.to_string_lossy().to_string())
 }
 
 /// can be used to copy or rename the image, delete meta file
-fn copy_asset(img: &String, name: &String) -> String {
+fn copy_asset(img: &String, name: &String) -> std::io::Result<String> {
     let asset_path = Path::new("./assets/");
 
     let image_path = Path::new(&img);
+    if let Some(folder) = model_folder(image_path) {
+        return move_model_folder(image_path, folder, name, true);
+    }
     let file_ext = image_path
         .extension()
         .and_then(|s| s.to_str())
@@ -248,17 +759,19 @@ fn copy_asset(img: &String, name: &String) -> String {
     let src = asset_path.join(&image_path);
     let dst = asset_path.join(&new_image_path);
 
-    dbg!("Copying asset from {:?} to {:?}", &src, &dst);
-    std::fs::copy(&src, &dst).unwrap_or_default();
+    debug!("Copying asset from {:?} to {:?}", src, dst);
+    std::fs::copy(&src, &dst)?;
 
-    let path = new_image_path.to_str().unwrap().to_string();
-    path
+    Ok(new_image_path.to_string_lossy().to_string())
 }
 
-fn rename_asset(img: &String, name: &String) -> String {
+fn rename_asset(img: &String, name: &String) -> std::io::Result<String> {
     let asset_path = Path::new("./assets/");
 
     let image_path = Path::new(&img);
+    if let Some(folder) = model_folder(image_path) {
+        return move_model_folder(image_path, folder, name, false);
+    }
     let file_ext = image_path
         .extension()
         .and_then(|s| s.to_str())
@@ -268,11 +781,10 @@ fn rename_asset(img: &String, name: &String) -> String {
     let src = asset_path.join(&image_path);
     let dst = asset_path.join(&new_image_path);
 
-    dbg!("Renaming asset from {:?} to {:?}", &src, &dst);
-    std::fs::rename(&src, &dst).unwrap_or_default();
+    debug!("Renaming asset from {:?} to {:?}", src, dst);
+    std::fs::rename(&src, &dst)?;
 
-    let path = new_image_path.to_str().unwrap().to_string();
-    path
+    Ok(new_image_path.to_string_lossy().to_string())
 }
 
 fn remove_numeric_suffix(name: String) -> String {
@@ -293,26 +805,29 @@ fn on_delete(trigger: Trigger<Delete>, mut commands: Commands, query: Query<&Pre
 
     let prefab = query.get(entity).unwrap();
 
-    match &prefab.workflow {
-        Workflow::StaticImage { image } => {
-            if let Some(img) = image {
-                std::fs::remove_file(Path::new("assets").join(img)).unwrap_or_default();
-            }
-        }
-        Workflow::TextToImage { image, .. } => {
-            if let Some(img) = image {
-                std::fs::remove_file(Path::new("assets").join(img)).unwrap_or_default();
+    for file in [prefab.workflow.image(), prefab.workflow.model()]
+        .into_iter()
+        .flatten()
+    {
+        match model_folder(Path::new(file)) {
+            // only the prefab's own ref/<name> folder, a model left elsewhere by a failed
+            // rename or copy may be shared
+            Some(folder) if folder == Path::new("ref").join(&prefab.name) => {
+                std::fs::remove_dir_all(Path::new("assets").join(folder))
             }
-        }
-        Workflow::TextToModel { image, model, .. } => {
-            if let Some(img) = image {
-                std::fs::remove_file(Path::new("assets").join(img)).unwrap_or_default();
-            }
-            if let Some(m) = model {
-                std::fs::remove_file(Path::new("assets").join(m)).unwrap_or_default();
+            Some(folder) => {
+                warn!(
+                    "Not deleting {:?}, it is not {}'s folder",
+                    folder, prefab.name
+                );
+                Ok(())
             }
+            None => std::fs::remove_file(Path::new("assets").join(file)),
         }
+        .unwrap_or_default();
     }
+    remove_variants(&prefab.variants);
+    remove_history(&prefab.history);
 
     commands.entity(entity).despawn();
 }
@@ -323,42 +838,44 @@ pub struct Rename(pub String);
 pub fn on_rename(trigger: Trigger<Rename>, mut query: Query<&mut Prefab>) {
     let entity = trigger.target();
     let mut new_name = trigger.0.clone();
+    // the name becomes a file and folder name in ref
+    if new_name.trim().is_empty() || new_name.contains(['/', '\\']) || new_name.contains("..") {
+        warn!("{:?} can't be used as a prefab name", new_name);
+        return;
+    }
     let names = query.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
     new_name = create_unique_name(&new_name, names);
 
     let mut prefab = query.get_mut(entity).unwrap();
     prefab.name = new_name.clone();
 
-    match &mut prefab.workflow {
-        Workflow::TextToImage { image, .. } => {
-            if let Some(img) = image {
-                *img = rename_asset(&img, &new_name);
-            }
-        }
-        Workflow::TextToModel { image, model, .. } => {
-            if let Some(img) = image {
-                *img = rename_asset(&img, &new_name);
-            }
-            if let Some(m) = model {
-                *m = rename_asset(&m, &new_name);
-            }
-        }
-        Workflow::StaticImage { image } => {
-            if let Some(img) = image {
-                *img = rename_asset(&img, &new_name);
-            }
+    for file in prefab.workflow.files_mut() {
+        match rename_asset(file, &new_name) {
+            Ok(new_file) => *file = new_file,
+            Err(err) => error!("Failed to rename {} to {}: {}", file, new_name, err),
         }
     }
+    // a new prefab taking the old name would overwrite them otherwise
+    rename_variants(&mut prefab.variants, &new_name);
 }
 
-fn create_unique_name(new_name: &String, names: Vec<String>) -> String {
+/// folders in ref shared by all prefabs, a gltf or obj prefab with this name would take them
+const RESERVED_NAMES: &[&str] = &["history", "variants"];
+
+/// a name no other prefab has and that is free as a folder in ref
+pub fn create_unique_name(new_name: &String, names: Vec<String>) -> String {
+    let taken = |name: &String| {
+        names.contains(name)
+            || RESERVED_NAMES.contains(&name.as_str())
+            || Path::new("assets/ref").join(name).exists()
+    };
     let mut new_name = new_name.clone();
-    if names.contains(&new_name) {
+    if taken(&new_name) {
         // Rename
         let short_name = remove_numeric_suffix(new_name.clone());
         let mut name = short_name.clone();
         let mut i = 1;
-        while names.contains(&name) {
+        while taken(&name) {
             i += 1;
             name = format!("{}_{}", short_name, i);
         }
@@ -371,181 +888,759 @@ fn create_unique_name(new_name: &String, names: Vec<String>) -> String {
 #[derive(Event)]
 pub struct Generate(pub Option<u8>);
 
-// TODO: setup history tracking, or progress from api
-const IMAGE_TIME: f32 = 10.0;
-const MODEL_TIME: f32 = 90.0;
-
 pub fn on_generate(
     trigger: Trigger<Generate>,
     mut query: Query<&mut Prefab>,
-    runtime: ResMut<TokioTasksRuntime>,
+    mut queue: ResMut<GenerationQueue>,
     mut rng: GlobalEntropy<WyRand>,
     mut commands: Commands,
 ) {
     let e = trigger.target();
     let mut prefab = query.get_mut(e).unwrap();
     let stage = trigger.0;
-    let name = prefab.name.clone();
+
+    // texturing paints the approved shape like a retexture, rebuilding it from the seed
+    // would count on ComfyUI still having the same mesh cached
+    let mut kind = JobKind::Generate;
+    if stage == Some(TEXTURE_STAGE) && prefab.workflow.mesh().is_some() {
+        let Some(retexture) = texture_job(&prefab) else {
+            return;
+        };
+        kind = JobKind::Retexture(retexture);
+    }
+
+    // seeds are picked now so the queued job is exactly what was asked for
     match &mut prefab.workflow {
-        Workflow::StaticImage { .. } => {}
+        Workflow::StaticImage { .. } | Workflow::StaticModel { .. } => return,
+        Workflow::ImageToModel { image: None, .. } => {
+            warn!("{} has no image to make a model from", prefab.name);
+            return;
+        }
+        Workflow::MultiViewToModel {
+            views: ModelViews { front: None, .. },
+            image: None,
+            ..
+        } => {
+            warn!("{} has no front view to make a model from", prefab.name);
+            return;
+        }
+        // texturing keeps the approved shape's seed
+        Workflow::TextToModel { .. }
+        | Workflow::ImageToModel { .. }
+        | Workflow::MultiViewToModel { .. }
+            if stage == Some(TEXTURE_STAGE) => {}
         Workflow::TextToImage {
-            image,
-            seed,
-            seed_random,
-            prompt,
+            seed, seed_random, ..
+        }
+        | Workflow::TextToModel {
+            seed, seed_random, ..
+        }
+        | Workflow::ImageToModel {
+            seed, seed_random, ..
+        }
+        | Workflow::MultiViewToModel {
+            seed, seed_random, ..
         } => {
-            commands.entity(e).insert(WorkflowProgress {
-                timer: Timer::new(Duration::from_secs_f32(IMAGE_TIME), TimerMode::Once),
-            });
+            update_seed(&mut rng, seed, seed_random);
+        }
+        Workflow::Custom { params, .. } => {
+            for value in params.values_mut() {
+                if let ParamValue::Seed { seed, random } = value {
+                    update_seed(&mut rng, seed, random);
+                }
+            }
+        }
+    }
 
-            let start = Instant::now();
-            // only 1 stage here
-            let image_path = get_image_path(&name, image);
-            let new_seed = update_seed(&mut rng, seed, seed_random);
-            let prompt = prompt.clone();
-            runtime.spawn_background_task(async move |mut ctx| {
-                generate_image(&name, &image_path, new_seed, &prompt)
-                    .await
-                    .unwrap();
-
-                tokio::time::sleep(Duration::from_secs_f32(FILE_DELAY)).await;
-
-                ctx.run_on_main_thread(move |ctx| {
-                    ctx.world
-                        .trigger_targets(RefreshImage(image_path.clone()), e);
-                    ctx.world.entity_mut(e).remove::<WorkflowProgress>();
-                    let end = Instant::now();
-                    info!("TextToImage generated in {:?}", end.duration_since(start));
+    commands.entity(e).remove::<GenerationFailed>();
+    if !queue.is_running(e) {
+        commands
+            .entity(e)
+            .insert(WorkflowProgress::new(stage_count(&prefab.workflow, stage)));
+    }
+    queue.push(GenerationJob {
+        entity: e,
+        name: prefab.name.clone(),
+        workflow: prefab.workflow.clone(),
+        stage,
+        priority: 0,
+        kind,
+    });
+}
+
+/// paints the model made by the shape stage from the image it was made from
+fn texture_job(prefab: &Prefab) -> Option<RetextureJob> {
+    let (seed, reference) = match &prefab.workflow {
+        Workflow::TextToModel { seed, image, .. } | Workflow::ImageToModel { seed, image, .. } => {
+            (*seed, image.clone())
+        }
+        Workflow::MultiViewToModel {
+            seed, image, views, ..
+        } => (*seed, views.front.clone().or_else(|| image.clone())),
+        _ => return None,
+    };
+    // the shape is uploaded and the result written back over it as glb
+    if !prefab.workflow.model().is_some_and(|m| m.ends_with(".glb")) {
+        warn!(
+            "{} has no shape to texture, generate the shape first",
+            prefab.name
+        );
+        return None;
+    }
+    let Some(reference) = reference else {
+        warn!("{} has no image to texture the shape from", prefab.name);
+        return None;
+    };
+    Some(RetextureJob { reference, seed })
+}
+
+fn stage_count(workflow: &Workflow, stage: Option<u8>) -> usize {
+    match (workflow, stage) {
+        (Workflow::TextToModel { .. }, None) => 2,
+        _ => 1,
+    }
+}
+
+/// starts queued jobs while there is room
+pub fn start_generations(
+    mut queue: ResMut<GenerationQueue>,
+    query: Query<&Prefab>,
+    runtime: Res<TokioTasksRuntime>,
+    comfy: Res<ComfyClient>,
+    mut progress: ResMut<ProgressChannel>,
+    templates: Res<WorkflowTemplates>,
+    template_assets: Res<Assets<WorkflowTemplate>>,
+    mut commands: Commands,
+) {
+    while let Some(job) = queue.next_ready() {
+        // prefab deleted while waiting
+        let Ok(prefab) = query.get(job.entity) else {
+            queue.finish(job.entity);
+            continue;
+        };
+        start_generation(
+            job,
+            prefab,
+            &mut queue,
+            &runtime,
+            &comfy,
+            &mut progress,
+            &templates,
+            &template_assets,
+            &mut commands,
+        );
+    }
+}
+
+fn start_generation(
+    job: GenerationJob,
+    prefab: &Prefab,
+    queue: &mut GenerationQueue,
+    runtime: &TokioTasksRuntime,
+    comfy: &ComfyClient,
+    progress: &mut ProgressChannel,
+    templates: &WorkflowTemplates,
+    template_assets: &Assets<WorkflowTemplate>,
+    commands: &mut Commands,
+) {
+    let e = job.entity;
+    let stage = job.stage;
+    // use the current name and files, the prefab could have been renamed while queued
+    let name = prefab.name.clone();
+    let image = prefab.workflow.image().cloned();
+    let model = prefab.workflow.model().cloned();
+    let stages = match job.kind {
+        JobKind::Generate => stage_count(&job.workflow, stage),
+        _ => 1,
+    };
+    let sender = progress.sender(e);
+    commands.entity(e).insert(WorkflowProgress {
+        job: sender.job(),
+        ..WorkflowProgress::new(stages)
+    });
+    let comfy = comfy.clone();
+    let progress = sender;
+    match job.kind {
+        JobKind::Generate => {}
+        JobKind::Inpaint(inpaint) => {
+            let image_path = get_image_path(&name, &image);
+            let models = job.workflow.models().cloned().unwrap_or_default();
+            let sampler = job.workflow.sampler().cloned().unwrap_or_default();
+            let keep_previous = !prefab.has_version(OutputKind::Image);
+            let template = templates.get(INPAINT_TEMPLATE, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "Inpaint",
+                move |_ctx| async move {
+                    let versions = generate_inpaint(
+                        &comfy,
+                        &progress,
+                        0,
+                        &template?,
+                        &name,
+                        &image_path,
+                        &inpaint,
+                        &models,
+                        &sampler,
+                        keep_previous,
+                    )
+                    .await?;
+                    Ok(Generated {
+                        image: Some(image_path),
+                        versions,
+                        ..default()
+                    })
+                },
+            );
+            return;
+        }
+        JobKind::Retexture(retexture) => {
+            let model_path = get_model_path(&name, &model);
+            let mesh = job.workflow.mesh().cloned().unwrap_or_default();
+            let keep_previous = !prefab.has_version(OutputKind::Model);
+            let template = templates.get(RETEXTURE_TEMPLATE, template_assets).cloned();
+            // the texture stage of the model workflows runs as a retexture of its shape
+            let label = if stage == Some(TEXTURE_STAGE) {
+                "Texture"
+            } else {
+                "Retexture"
+            };
+            spawn_generation(runtime, commands, e, stage, label, move |_ctx| async move {
+                let versions = generate_retexture(
+                    &comfy,
+                    &progress,
+                    0,
+                    &template?,
+                    &name,
+                    &model_path,
+                    &retexture,
+                    &mesh,
+                    keep_previous,
+                    label,
+                )
+                .await?;
+                Ok(Generated {
+                    model: Some(model_path),
+                    versions,
+                    ..default()
                 })
-                .await;
             });
+            return;
+        }
+    }
+    match job.workflow {
+        Workflow::StaticImage { .. } | Workflow::StaticModel { .. } => {
+            // nothing to generate, never queued by on_generate
+            queue.finish(e);
+            commands.entity(e).remove::<WorkflowProgress>();
+        }
+        Workflow::TextToImage {
+            seed,
+            prompt,
+            models,
+            sampler,
+            img2img,
+            ..
+        } => {
+            // only 1 stage here
+            let image_path = get_image_path(&name, &image);
+            let (template_name, img2img) = if img2img.enabled {
+                (IMG2IMG_TEMPLATE, Some(img2img.denoise))
+            } else {
+                (IMAGE_TEMPLATE, None)
+            };
+            // a missing template fails the generation like any other error
+            let template = templates.get(template_name, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "TextToImage",
+                move |_ctx| async move {
+                    let (version, variants) = generate_image(
+                        &comfy,
+                        &progress,
+                        0,
+                        &template?,
+                        &name,
+                        &image_path,
+                        seed,
+                        &prompt,
+                        &models,
+                        &sampler,
+                        img2img,
+                    )
+                    .await?;
+                    Ok(Generated {
+                        image: Some(image_path),
+                        variants: Some(variants),
+                        versions: vec![version],
+                        ..default()
+                    })
+                },
+            );
         }
         Workflow::TextToModel {
-            image,
             seed,
-            seed_random,
             prompt,
-            model,
             num_faces,
+            models,
+            sampler,
+            mesh,
+            ..
         } => {
-            commands.entity(e).insert(WorkflowProgress {
-                timer: Timer::new(
-                    Duration::from_secs_f32(match stage {
-                        Some(x) => match x {
-                            0 => IMAGE_TIME,
-                            1 => MODEL_TIME,
-                            _ => IMAGE_TIME + MODEL_TIME,
-                        },
-                        None => IMAGE_TIME + MODEL_TIME,
-                    }),
-                    TimerMode::Once,
-                ),
-            });
-            let start = Instant::now();
-
-            let image_path = get_image_path(&name, image);
-            let model_path = get_model_path(&name, model);
-            let new_seed = update_seed(&mut rng, seed, seed_random);
-            let prompt = prompt.clone();
-            let num_faces = *num_faces;
-            runtime.spawn_background_task(async move |mut ctx: TaskContext| {
-                // if stage is None, or stage == Some(0) run image
-                if stage.is_none() || stage == Some(0) {
-                    generate_image(&name, &image_path, new_seed, &prompt)
-                        .await
-                        .unwrap();
-
-                    tokio::time::sleep(Duration::from_secs_f32(FILE_DELAY)).await;
-
-                    let image_path_c = image_path.clone();
-                    ctx.run_on_main_thread(move |ctx| {
-                        ctx.world
-                            .trigger_targets(RefreshImage(image_path_c.clone()), e);
-
-                        let stage0 = Instant::now();
-                        info!("TextToImage stage 0 in {:?}", stage0.duration_since(start));
+            let image_path = get_image_path(&name, &image);
+            let model_path = get_model_path(&name, &model);
+            let image_template = templates.get(IMAGE_TEMPLATE, template_assets).cloned();
+            let model_template = templates.get(MODEL_TEMPLATE, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "TextToModel",
+                move |ctx| async move {
+                    // if stage is None, or stage == Some(0) run image
+                    if stage.is_none() || stage == Some(0) {
+                        let (version, variants) = generate_image(
+                            &comfy,
+                            &progress,
+                            0,
+                            &image_template?,
+                            &name,
+                            &image_path,
+                            seed,
+                            &prompt,
+                            &models,
+                            &sampler,
+                            None,
+                        )
+                        .await?;
+                        let generated = Generated {
+                            image: Some(image_path.clone()),
+                            variants: Some(variants),
+                            versions: vec![version],
+                            ..default()
+                        };
+                        if stage == Some(0) {
+                            return Ok(generated);
+                        }
+                        // show the image while the model is made
+                        generated.refresh_after_delay(ctx, e).await;
+                    }
+
+                    // shape or texture stage
+                    let index = if stage.is_none() { 1 } else { 0 };
+                    let version = generate_model(
+                        &comfy,
+                        &progress,
+                        index,
+                        &model_template?,
+                        &name,
+                        &image_path,
+                        &model_path,
+                        seed,
+                        num_faces,
+                        &mesh,
+                        mesh.shape_only(stage),
+                    )
+                    .await?;
+                    Ok(Generated {
+                        model: Some(model_path),
+                        versions: vec![version],
+                        ..default()
                     })
-                    .await;
-                }
+                },
+            );
+        }
+        Workflow::ImageToModel {
+            seed,
+            num_faces,
+            mesh,
+            ..
+        } => {
+            let image_path = get_image_path(&name, &image);
+            let model_path = get_model_path(&name, &model);
+            let template = templates.get(MODEL_TEMPLATE, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "ImageToModel",
+                move |_ctx| async move {
+                    let version = generate_model(
+                        &comfy,
+                        &progress,
+                        0,
+                        &template?,
+                        &name,
+                        &image_path,
+                        &model_path,
+                        seed,
+                        num_faces,
+                        &mesh,
+                        mesh.shape_only(stage),
+                    )
+                    .await?;
+                    Ok(Generated {
+                        model: Some(model_path),
+                        versions: vec![version],
+                        ..default()
+                    })
+                },
+            );
+        }
+        Workflow::MultiViewToModel {
+            seed,
+            num_faces,
+            views,
+            mesh,
+            ..
+        } => {
+            let views = views.images(image.as_ref());
+            let model_path = get_model_path(&name, &model);
+            let template = templates.get(MULTIVIEW_TEMPLATE, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "MultiViewToModel",
+                move |_ctx| async move {
+                    let version = generate_multiview(
+                        &comfy,
+                        &progress,
+                        0,
+                        &template?,
+                        &name,
+                        &views,
+                        &model_path,
+                        seed,
+                        num_faces,
+                        &mesh,
+                        mesh.shape_only(stage),
+                    )
+                    .await?;
+                    Ok(Generated {
+                        model: Some(model_path),
+                        versions: vec![version],
+                        ..default()
+                    })
+                },
+            );
+        }
+        Workflow::Custom {
+            template, params, ..
+        } => {
+            let image_path = get_image_path(&name, &image);
+            let model_path = get_model_path(&name, &model);
+            let template = templates.get(&template, template_assets).cloned();
+            spawn_generation(
+                runtime,
+                commands,
+                e,
+                stage,
+                "Custom",
+                move |_ctx| async move {
+                    let (written, versions) = generate_custom(
+                        &comfy,
+                        &progress,
+                        0,
+                        &template?,
+                        &name,
+                        &params,
+                        &image_path,
+                        &model_path,
+                    )
+                    .await?;
+                    Ok(Generated {
+                        image: written.image,
+                        model: written.model,
+                        versions,
+                        ..default()
+                    })
+                },
+            );
+        }
+    }
+}
 
-                if stage.is_none() || stage == Some(1) {
-                    generate_model(&name, &image_path, &model_path, new_seed, num_faces)
-                        .await
-                        .unwrap();
+/// files a generation wrote, refreshed on the prefab once it is done
+#[derive(Default)]
+struct Generated {
+    image: Option<String>,
+    model: Option<String>,
+    /// the rest of an image batch, None leaves the variants as they are
+    variants: Option<Vec<String>>,
+    /// oldest first
+    versions: Vec<Version>,
+}
 
-                    tokio::time::sleep(Duration::from_secs_f32(FILE_DELAY)).await;
+impl Generated {
+    fn apply(self, world: &mut World, e: Entity) {
+        if let Some(image) = self.image {
+            world.trigger_targets(RefreshImage(image), e);
+        }
+        if let Some(model) = self.model {
+            world.trigger_targets(RefreshModel(model), e);
+        }
+        // an empty batch clears the last one's variants
+        if let Some(variants) = self.variants {
+            world.trigger_targets(SetVariants(variants), e);
+        }
+        for version in self.versions {
+            world.trigger_targets(AddVersion(version), e);
+        }
+    }
 
-                    ctx.run_on_main_thread(move |ctx| {
-                        ctx.world
-                            .trigger_targets(RefreshModel(model_path.clone()), e);
-                        let end = Instant::now();
-                        info!("TextToImage generated in {:?}", end.duration_since(start));
-                    })
-                    .await;
-                }
-                ctx.run_on_main_thread(move |ctx| {
-                    ctx.world.entity_mut(e).remove::<WorkflowProgress>();
-                })
-                .await;
-            });
+    /// waits for the files to be written out, then refreshes on the main thread
+    async fn refresh_after_delay(self, mut ctx: TaskContext, e: Entity) {
+        tokio::time::sleep(Duration::from_secs_f32(FILE_DELAY)).await;
+        ctx.run_on_main_thread(move |ctx| self.apply(ctx.world, e))
+            .await;
+    }
+}
+
+/// runs `generate` in the background, then refreshes what it wrote and clears the progress,
+/// the task gets its own context to refresh between stages
+fn spawn_generation<F, Fut>(
+    runtime: &TokioTasksRuntime,
+    commands: &mut Commands,
+    e: Entity,
+    stage: Option<u8>,
+    label: &'static str,
+    generate: F,
+) where
+    F: FnOnce(TaskContext) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Generated, ComfyError>> + Send + 'static,
+{
+    let start = Instant::now();
+    let task = runtime.spawn_background_task(async move |mut ctx| {
+        let result = generate(ctx.clone()).await;
+        if result.is_ok() {
+            tokio::time::sleep(Duration::from_secs_f32(FILE_DELAY)).await;
         }
+
+        ctx.run_on_main_thread(move |ctx| {
+            let result = result.map(|generated| {
+                generated.apply(ctx.world, e);
+                info!("{} generated in {:?}", label, start.elapsed());
+            });
+            finish_generation(ctx.world, e, stage, result);
+        })
+        .await;
+    });
+    commands
+        .entity(e)
+        .insert(GenerationTask(task.abort_handle()));
+}
+
+/// Set when the last generation for a prefab failed, cleared by the next one
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct GenerationFailed {
+    /// stage that was requested, used to retry
+    pub stage: Option<u8>,
+    /// what the failed job did, retried as is
+    pub kind: JobKind,
+    pub error: String,
+}
+
+/// reruns whatever failed last, generations pick up the prefab's current settings
+#[derive(Event)]
+pub struct Retry;
+
+pub fn on_retry(
+    trigger: Trigger<Retry>,
+    query: Query<(&Prefab, &GenerationFailed)>,
+    mut queue: ResMut<GenerationQueue>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let Ok((prefab, failed)) = query.get(e) else {
+        return;
+    };
+    if let JobKind::Generate = failed.kind {
+        commands.trigger_targets(Generate(failed.stage), e);
+        return;
+    }
+    let kind = failed.kind.clone();
+    commands.entity(e).remove::<GenerationFailed>();
+    if !queue.is_running(e) {
+        commands.entity(e).insert(WorkflowProgress::new(1));
+    }
+    queue.push(GenerationJob {
+        entity: e,
+        name: prefab.name.clone(),
+        workflow: prefab.workflow.clone(),
+        stage: failed.stage,
+        priority: 0,
+        kind,
+    });
+}
+
+/// Background task running a generation for this prefab
+#[derive(Component)]
+pub struct GenerationTask(pub AbortHandle);
+
+#[derive(Event)]
+pub struct Cancel;
+
+/// stops the task, its [`crate::PromptGuard`] removes the prompt from ComfyUI,
+/// nothing is written so the current image and model stay as they were
+pub fn on_cancel(
+    trigger: Trigger<Cancel>,
+    query: Query<&GenerationTask>,
+    mut queue: ResMut<GenerationQueue>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    queue.remove(e);
+    queue.finish(e);
+    if let Ok(task) = query.get(e) {
+        task.0.abort();
+    }
+    commands
+        .entity(e)
+        .remove::<(WorkflowProgress, GenerationTask)>();
+}
+
+/// clears progress and records any error on the prefab
+fn finish_generation(
+    world: &mut World,
+    e: Entity,
+    stage: Option<u8>,
+    result: Result<(), ComfyError>,
+) {
+    // prefab could have been deleted while generating
+    let kind = world
+        .resource_mut::<GenerationQueue>()
+        .finish(e)
+        .map(|job| job.kind)
+        .unwrap_or_default();
+    let Ok(mut entity) = world.get_entity_mut(e) else {
+        return;
+    };
+    entity.remove::<(WorkflowProgress, GenerationTask)>();
+    if let Err(err) = result {
+        error!("Generation failed: {}", err);
+        entity.insert(GenerationFailed {
+            stage,
+            kind,
+            error: err.to_string(),
+        });
     }
 }
 
 async fn generate_image(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
     name: &String,
     image_path: &String,
-    new_seed: u32,
+    new_seed: u64,
     prompt: &String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse the JSON workflow.
-    let mut workflow: Value = serde_json::from_str(include_str!("workflows/ref_image_gen.json"))?;
+    models: &ModelSettings,
+    sampler: &SamplerSettings,
+    img2img: Option<f32>,
+) -> Result<(Version, Vec<String>), ComfyError> {
+    let start = Instant::now();
+    let mut workflow = template.instantiate();
+    models.apply(template, &mut workflow);
+    sampler.apply(template, &mut workflow);
+
+    // img2img starts from the current image, overwritten by the result
+    if let Some(denoise) = img2img {
+        let filename = Path::new(&image_path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let file_path = Path::new("assets")
+            .join(&image_path)
+            .to_string_lossy()
+            .to_string();
+        comfy.upload_image(file_path, filename.clone()).await?;
+        template.set(&mut workflow, "image", &filename);
+        template.set(&mut workflow, "denoise", denoise);
+    }
 
     // update the seed
-    if let Some(text_value) = workflow.pointer_mut("/9/inputs/seed") {
-        *text_value = json!(new_seed);
-    }
+    template.set(&mut workflow, "seed", new_seed);
     // update the prompt text
-    if let Some(text_value) = workflow.pointer_mut("/11/inputs/text") {
-        *text_value = json!(prompt);
-    }
+    template.set(&mut workflow, "prompt", prompt);
     // update save_path
-    if let Some(text_value) = workflow.pointer_mut("/13/inputs/value") {
-        *text_value = json!(name);
-    }
-
-    // Connect to the websocket.
-    let (client, client_id, mut ws) = comfy::connect_comfy().await.unwrap();
+    template.set(&mut workflow, "save_name", name);
 
+    progress.stage(stage_index, node_count(&workflow));
     // Wait for execution to complete and download the images.
-    let images = comfy::get_images(&mut ws, &client, &workflow, &client_id).await?;
-    assert!(images.len() == 1, "Wrong number of images generated");
-
-    ws.close(None).await?;
-
-    for (_node_id, images_vec) in images.iter() {
-        for (_i, image_data) in images_vec.iter().enumerate() {
-            let file_path = Path::new("assets").join(&image_path);
-            tokio::fs::write(&file_path, image_data).await?;
+    let images = comfy
+        .get_images(&workflow, |p| progress.comfy(p))
+        .await?;
+
+    // prefer the declared output, otherwise whatever node saved something
+    let batch = template
+        .output("image")
+        .and_then(|node| images.get(node))
+        .or_else(|| images.values().find(|v| !v.is_empty()))
+        .filter(|v| !v.is_empty())
+        .ok_or(ComfyError::MissingOutput("image".to_string()))?;
+
+    let file_path = Path::new("assets").join(&image_path);
+    write_output(&file_path, &batch[0], template, &workflow).await?;
+    let label = if img2img.is_some() {
+        "Img2Img"
+    } else {
+        "Image"
+    };
+    let version = record_version(
+        template,
+        &workflow,
+        &file_path,
+        OutputKind::Image,
+        label,
+        start,
+    )
+    .await?;
+
+    // the rest of a batch are variants to pick from
+    let mut variants = Vec::new();
+    for (i, image_data) in batch.iter().enumerate().skip(1) {
+        let variant = variant_path(name, i);
+        let file_path = Path::new("assets").join(&variant);
+        if let Some(dir) = file_path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
         }
+        write_output(&file_path, image_data, template, &workflow).await?;
+        variants.push(variant);
     }
-    Ok(())
+    Ok((version, variants))
 }
 
 async fn generate_model(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
     name: &String,
     image_path: &String,
     model_path: &String,
-    new_seed: u32,
+    new_seed: u64,
     num_faces: u32,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Parse the JSON workflow.
-    let mut workflow: Value = serde_json::from_str(include_str!("workflows/ref_3d_gen.json"))?;
+    mesh: &MeshSettings,
+    shape_only: bool,
+) -> Result<Version, ComfyError> {
+    let mut workflow = template.instantiate();
+    mesh.apply(template, &mut workflow);
 
     // update the seed gen mesh
-    if let Some(text_value) = workflow.pointer_mut("/141/inputs/seed") {
-        *text_value = json!(new_seed);
-    }
+    template.set(&mut workflow, "seed", new_seed);
 
     // update input image with what we call it when we upload it
     let filename = Path::new(&image_path)
@@ -553,50 +1648,358 @@ async fn generate_model(
         .unwrap()
         .to_string_lossy()
         .to_string();
-    if let Some(text_value) = workflow.pointer_mut("/174/inputs/image") {
-        *text_value = json!(&filename);
-    }
+    template.set(&mut workflow, "image", &filename);
 
     // update save_path
-    if let Some(text_value) = workflow.pointer_mut("/175/inputs/value") {
-        *text_value = json!(name);
+    template.set(&mut workflow, "save_name", name);
+    template.set(&mut workflow, "num_faces", num_faces);
+
+    // upload image
+    let file_path = Path::new("assets")
+        .join(&image_path)
+        .to_string_lossy()
+        .to_string();
+    comfy.upload_image(file_path, filename.clone()).await?;
+    save_model(comfy, progress, stage_index, template, workflow, shape_only, model_path).await
+}
+
+/// uploads every linked view, views left out are unlinked from the mesh generation
+async fn generate_multiview(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
+    name: &String,
+    views: &[(&'static str, Option<String>)],
+    model_path: &String,
+    new_seed: u64,
+    num_faces: u32,
+    mesh: &MeshSettings,
+    shape_only: bool,
+) -> Result<Version, ComfyError> {
+    let mut workflow = template.instantiate();
+    mesh.apply(template, &mut workflow);
+
+    template.set(&mut workflow, "seed", new_seed);
+    template.set(&mut workflow, "save_name", name);
+    template.set(&mut workflow, "num_faces", num_faces);
+
+    for (view, image) in views {
+        let Some(image) = image else {
+            template.unset_optional(&mut workflow, view);
+            continue;
+        };
+        let filename = Path::new(image)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let file_path = Path::new("assets").join(image).to_string_lossy().to_string();
+        comfy.upload_image(file_path, filename.clone()).await?;
+        template.set(&mut workflow, view, &filename);
     }
 
-    if let Some(text_value) = workflow.pointer_mut("/59/inputs/max_facenum") {
-        *text_value = json!(num_faces);
+    save_model(comfy, progress, stage_index, template, workflow, shape_only, model_path).await
+}
+
+/// runs a model workflow and saves the first model it exports,
+/// `shape_only` drops everything but the untextured "shape" output
+async fn save_model(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
+    mut workflow: Value,
+    shape_only: bool,
+    model_path: &String,
+) -> Result<Version, ComfyError> {
+    let start = Instant::now();
+    let output = if shape_only { "shape" } else { "model" };
+    let model_node = template.output(output).ok_or(ComfyError::MissingOutput(format!(
+        "{} output in {}",
+        output, template.name
+    )))?;
+    if shape_only {
+        prune_workflow(&mut workflow, &[model_node]);
     }
 
-    // Connect to the websocket.
-    let (client, client_id, mut ws) = comfy::connect_comfy().await.unwrap();
+    progress.stage(stage_index, node_count(&workflow));
+    // Wait for execution to complete and download the images.
+    let models = comfy
+        .get_models(&workflow, model_node, |p| progress.comfy(p))
+        .await?;
+
+    let models = models
+        .get(model_node)
+        .filter(|v| !v.is_empty())
+        .ok_or(ComfyError::MissingOutput("model".to_string()))?;
+    if models.len() > 1 {
+        warn!("more than one model generated, only keeping first");
+    }
+    let file_path = Path::new("assets").join(&model_path);
+    write_output(&file_path, &models[0], template, &workflow).await?;
+    info!("Saved model to {:?}", file_path);
+    let label = if shape_only { "Shape" } else { "Model" };
+    record_version(
+        template,
+        &workflow,
+        &file_path,
+        OutputKind::Model,
+        label,
+        start,
+    )
+    .await
+}
 
-    // upload image
+/// sets every param the template declares, uploading board images, then saves
+/// the first image and model outputs, returns the files written
+async fn generate_custom(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
+    name: &String,
+    params: &BTreeMap<String, ParamValue>,
+    image_path: &String,
+    model_path: &String,
+) -> Result<(OutputSlots, Vec<Version>), ComfyError> {
+    let start = Instant::now();
+    let mut workflow = template.instantiate();
+    template.set(&mut workflow, "save_name", name);
+
+    for (param, value) in params.iter() {
+        match value {
+            ParamValue::Image(Some(path)) => {
+                let filename = Path::new(path)
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                let file_path = Path::new("assets").join(path).to_string_lossy().to_string();
+                comfy.upload_image(file_path, filename.clone()).await?;
+                template.set(&mut workflow, param, &filename);
+            }
+            // keep whatever the workflow was saved with, unless the manifest says it can be left out
+            ParamValue::Image(None) => {
+                template.unset_optional(&mut workflow, param);
+            }
+            value => {
+                if let Some(json) = value.to_json() {
+                    template.set(&mut workflow, param, json);
+                }
+            }
+        }
+    }
+
+    progress.stage(stage_index, node_count(&workflow));
+    let nodes = template.outputs().collect::<Vec<_>>();
+    let files = comfy
+        .get_outputs(&workflow, &nodes, |p| progress.comfy(p))
+        .await?;
+
+    let mut written = OutputSlots::default();
+    let mut versions = Vec::new();
+    for (node, kind) in nodes {
+        let Some(data) = files.get(node).and_then(|v| v.first()) else {
+            continue;
+        };
+        let (slot, path) = match kind {
+            OutputKind::Image => (&mut written.image, image_path),
+            OutputKind::Model => (&mut written.model, model_path),
+        };
+        if slot.is_some() {
+            warn!("more than one {:?} output in {}, only keeping first", kind, template.name);
+            continue;
+        }
+        let file_path = Path::new("assets").join(path);
+        write_output(&file_path, data, template, &workflow).await?;
+        let version =
+            record_version(template, &workflow, &file_path, kind, &template.name, start).await?;
+        versions.push(version);
+        *slot = Some(path.clone());
+    }
+    if written.image.is_none() && written.model.is_none() {
+        return Err(ComfyError::MissingOutput(format!("outputs of {}", template.name)));
+    }
+    Ok((written, versions))
+}
+
+/// uploads the image and mask, then replaces the image with the result,
+/// `keep_previous` records the old image first when it was never generated here
+async fn generate_inpaint(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
+    name: &String,
+    image_path: &String,
+    inpaint: &InpaintJob,
+    models: &ModelSettings,
+    sampler: &SamplerSettings,
+    keep_previous: bool,
+) -> Result<Vec<Version>, ComfyError> {
+    let start = Instant::now();
+    let mut workflow = template.instantiate();
+    models.apply(template, &mut workflow);
+    sampler.apply(template, &mut workflow);
+
+    let path = Path::new(&image_path);
+    let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
     let file_path = Path::new("assets")
         .join(&image_path)
         .to_string_lossy()
         .to_string();
-    comfy::upload_image(&client, file_path, filename.clone())
-        .await
-        .unwrap();
-    // Wait for execution to complete and download the images.
-    let models = comfy::get_models(&mut ws, &client, &workflow, &client_id, "154").await?;
+    comfy.upload_image(file_path, filename.clone()).await?;
+    // ComfyUI saves a copy of the image with the mask as its alpha
+    let masked = format!(
+        "{}_mask.png",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    comfy
+        .upload_mask(inpaint.mask.clone(), masked.clone(), filename)
+        .await?;
+    template.set(&mut workflow, "image", &masked);
+    template.set(&mut workflow, "grow_mask", inpaint.grow);
+
+    template.set(&mut workflow, "seed", inpaint.seed);
+    template.set(&mut workflow, "prompt", &inpaint.prompt);
+    template.set(&mut workflow, "save_name", name);
+
+    progress.stage(stage_index, node_count(&workflow));
+    let images = comfy
+        .get_images(&workflow, |p| progress.comfy(p))
+        .await?;
+
+    let image_data = template
+        .output("image")
+        .and_then(|node| images.get(node))
+        .or_else(|| images.values().find(|v| !v.is_empty()))
+        .and_then(|v| v.first())
+        .ok_or(ComfyError::MissingOutput("image".to_string()))?;
+
+    let file_path = Path::new("assets").join(&image_path);
+    let mut versions = Vec::new();
+    if keep_previous {
+        versions.extend(record_original(&file_path, OutputKind::Image).await?);
+    }
+    write_output(&file_path, image_data, template, &workflow).await?;
+    let version = record_version(
+        template,
+        &workflow,
+        &file_path,
+        OutputKind::Image,
+        "Inpaint",
+        start,
+    )
+    .await?;
+    versions.push(version);
+    Ok(versions)
+}
 
-    ws.close(None).await?;
-    //dbg!("models", &models);
+/// uploads the current model and paints it again from the reference image
+async fn generate_retexture(
+    comfy: &ComfyClient,
+    progress: &ProgressSender,
+    stage_index: usize,
+    template: &WorkflowTemplate,
+    name: &String,
+    model_path: &String,
+    retexture: &RetextureJob,
+    mesh: &MeshSettings,
+    keep_previous: bool,
+    label: &str,
+) -> Result<Vec<Version>, ComfyError> {
+    let start = Instant::now();
+    let mut workflow = template.instantiate();
+    mesh.apply(template, &mut workflow);
+
+    let model_file = Path::new("assets").join(&model_path);
+    let mesh_name = Path::new(&model_path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    comfy
+        .upload_mesh(model_file.to_string_lossy().to_string(), mesh_name.clone())
+        .await?;
+    // Hy3DLoadMesh opens a path, not a name in the input folder
+    template.set(&mut workflow, "mesh", comfy.input_path(&mesh_name));
 
-    for (_node_id, images_vec) in models.iter() {
-        for (i, image_data) in images_vec.iter().enumerate() {
-            let file_path = Path::new("assets").join(&model_path);
-            tokio::fs::write(&file_path, image_data).await?;
-            info!("Saved model to {:?}", file_path);
-            if i > 0 {
-                warn!("more than one model generated, only keeping first");
-                break;
-            } 
-        }
+    let filename = Path::new(&retexture.reference)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let file_path = Path::new("assets")
+        .join(&retexture.reference)
+        .to_string_lossy()
+        .to_string();
+    comfy.upload_image(file_path, filename.clone()).await?;
+    template.set(&mut workflow, "image", &filename);
+
+    template.set(&mut workflow, "seed", retexture.seed);
+    template.set(&mut workflow, "save_name", name);
+
+    let model_node = template
+        .output("model")
+        .ok_or(ComfyError::MissingOutput(format!("model output in {}", template.name)))?;
+    progress.stage(stage_index, node_count(&workflow));
+    let models = comfy
+        .get_models(&workflow, model_node, |p| progress.comfy(p))
+        .await?;
+    let model_data = models
+        .get(model_node)
+        .and_then(|v| v.first())
+        .ok_or(ComfyError::MissingOutput("model".to_string()))?;
+
+    let mut versions = Vec::new();
+    if keep_previous {
+        versions.extend(record_original(&model_file, OutputKind::Model).await?);
     }
+    write_output(&model_file, model_data, template, &workflow).await?;
+    let version = record_version(
+        template,
+        &workflow,
+        &model_file,
+        OutputKind::Model,
+        label,
+        start,
+    )
+    .await?;
+    versions.push(version);
+    Ok(versions)
+}
+
+/// writes a generated file with how it was made embedded in it
+async fn write_output(
+    path: &Path,
+    data: &[u8],
+    template: &WorkflowTemplate,
+    workflow: &Value,
+) -> Result<(), ComfyError> {
+    let data = GenerationMetadata::new(template, workflow).embed(path, data);
+    write_asset(path, &data).await
+}
+
+/// writes to a temp file first so a cancelled task never leaves half a file behind
+async fn write_asset(path: &Path, data: &[u8]) -> Result<(), ComfyError> {
+    let tmp = tmp_path(path);
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await?;
     Ok(())
 }
 
+/// foo.png -> foo.png.tmp, keeps the extension so foo.png and foo.glb never share one
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn node_count(workflow: &Value) -> usize {
+    workflow.as_object().map(|o| o.len()).unwrap_or_default()
+}
+
 fn get_image_path(name: &String, image: &Option<String>) -> String {
     let path = match image {
         Some(img) => img.clone(),
@@ -624,11 +2027,11 @@ fn get_model_path(name: &String, model: &Option<String>) -> String {
 // creates new seed and sets it if needed, returns the new seed
 fn update_seed(
     rng: &mut bevy_rand::prelude::Entropy<WyRand>,
-    seed: &mut u32,
+    seed: &mut u64,
     seed_random: &mut bool,
-) -> u32 {
+) -> u64 {
     let new_seed = if *seed_random {
-        let x = rng.r#gen::<u32>();
+        let x = rng.r#gen::<u64>();
         *seed = x;
         x
     } else {
@@ -650,21 +2053,8 @@ pub fn on_refresh_image(
     let e = trigger.target();
     let (mut p, mat_handle) = query.get_mut(e).unwrap();
     // update the image in the prefab
-    //let mut changed = false;
-    match &mut p.workflow {
-        Workflow::TextToModel { image, .. } => {
-            if *image != Some(trigger.0.clone()) {
-                //changed = true;
-                *image = Some(trigger.0.clone());
-            }
-        }
-        Workflow::TextToImage { image, .. } => {
-            if *image != Some(trigger.0.clone()) {
-                //changed = true;
-                *image = Some(trigger.0.clone());
-            }
-        }
-        _ => unreachable!(),
+    if p.workflow.image() != Some(&trigger.0) {
+        p.workflow.set_image(trigger.0.clone());
     }
 
     // TODO: shouldnt need to do this, but not reloading not working without
@@ -688,24 +2078,17 @@ pub fn on_refresh_model(
 ) {
     let e = trigger.target();
     let (mut p, children) = query.get_mut(e).unwrap();
-    //let mut changed = false;
     // update path
-    match &mut p.workflow {
-        Workflow::TextToModel { model, .. } => {
-            if *model != Some(trigger.0.clone()) {
-                //changed = true;
-                *model = Some(trigger.0.clone());
-            }
-        }
-        _ => unreachable!(),
+    if p.workflow.model() != Some(&trigger.0) {
+        p.workflow.set_model(trigger.0.clone());
     }
 
     // update the model
     // TODO: shouldnt need to do this
     //if changed {
     let child = children[0];
-    commands.entity(child).insert(SceneRoot(
-        asset_server.load(GltfAssetLabel::Scene(0).from_asset(trigger.0.clone())),
-    ));
+    commands
+        .entity(child)
+        .insert(SceneRoot(load_model(&asset_server, trigger.0.clone())));
     //}
 }
@@ -1,20 +1,265 @@
 use bevy::prelude::*;
 use bevy_health_bar3d::prelude::*;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
-#[derive(Component, Reflect)]
+use crate::ComfyProgress;
+
+/// Progress of a running generation, fed from the ComfyUI websocket
+#[derive(Component, Reflect, Debug, Default)]
 #[reflect()]
 pub struct WorkflowProgress {
-    pub timer: Timer,
+    /// job the updates have to come from, a cancelled or finished job can still be sending
+    pub job: u64,
+    /// prompt id of the current stage once queued
+    pub prompt_id: Option<String>,
+    /// number of prompts this generation will queue
+    pub stages: usize,
+    pub stage: usize,
+    /// nodes in the current prompt
+    pub nodes: usize,
+    /// finished or cached node ids in the current prompt
+    pub done: Vec<String>,
+    /// currently executing node id and title
+    pub node: Option<String>,
+    pub title: Option<String>,
+    /// step progress of the current node
+    pub value: u32,
+    pub max: u32,
+}
+
+impl WorkflowProgress {
+    pub fn new(stages: usize) -> Self {
+        Self {
+            stages: stages.max(1),
+            ..default()
+        }
+    }
+
+    /// progress of the current stage
+    pub fn stage_fraction(&self) -> f32 {
+        if self.nodes == 0 {
+            return 0.0;
+        }
+        let step = if self.max > 0 {
+            self.value as f32 / self.max as f32
+        } else {
+            0.0
+        };
+        ((self.done.len() as f32 + step) / self.nodes as f32).clamp(0.0, 1.0)
+    }
+
+    pub fn apply(&mut self, update: ProgressUpdate) {
+        match update {
+            ProgressUpdate::Stage { index, nodes } => {
+                self.stage = index;
+                self.nodes = nodes;
+                self.prompt_id = None;
+                self.done.clear();
+                self.node = None;
+                self.title = None;
+                self.value = 0;
+                self.max = 0;
+            }
+            ProgressUpdate::Comfy(ComfyProgress::Queued { prompt_id }) => {
+                self.prompt_id = Some(prompt_id);
+            }
+            ProgressUpdate::Comfy(ComfyProgress::Cached(nodes)) => {
+                for node in nodes {
+                    if !self.done.contains(&node) {
+                        self.done.push(node);
+                    }
+                }
+            }
+            ProgressUpdate::Comfy(ComfyProgress::Executing { node, title }) => {
+                // a new node starting means the last one finished
+                if let Some(prev) = self.node.take() {
+                    if !self.done.contains(&prev) {
+                        self.done.push(prev);
+                    }
+                }
+                self.node = Some(node);
+                self.title = title;
+                self.value = 0;
+                self.max = 0;
+            }
+            ProgressUpdate::Comfy(ComfyProgress::Step { node, value, max }) => {
+                if self.node.as_ref() != Some(&node) {
+                    self.node = Some(node);
+                    self.title = None;
+                }
+                self.value = value;
+                self.max = max;
+            }
+        }
+    }
 }
 
 impl Percentage for WorkflowProgress {
     fn value(&self) -> f32 {
-        self.timer.fraction()
+        ((self.stage as f32 + self.stage_fraction()) / self.stages as f32).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ProgressUpdate {
+    /// a new prompt was queued
+    Stage { index: usize, nodes: usize },
+    Comfy(ComfyProgress),
+}
+
+/// background tasks push progress here, drained each frame by [`update_progress`]
+#[derive(Resource)]
+pub struct ProgressChannel {
+    sender: UnboundedSender<(Entity, u64, ProgressUpdate)>,
+    receiver: UnboundedReceiver<(Entity, u64, ProgressUpdate)>,
+    last_job: u64,
+}
+
+impl Default for ProgressChannel {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            sender,
+            receiver,
+            last_job: 0,
+        }
+    }
+}
+
+impl ProgressChannel {
+    /// every sender is a new job, put its id on the prefab's [`WorkflowProgress`]
+    pub fn sender(&mut self, entity: Entity) -> ProgressSender {
+        self.last_job += 1;
+        ProgressSender {
+            entity,
+            job: self.last_job,
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+/// handle given to a background task to report progress for one prefab
+#[derive(Clone)]
+pub struct ProgressSender {
+    entity: Entity,
+    job: u64,
+    sender: UnboundedSender<(Entity, u64, ProgressUpdate)>,
+}
+
+impl ProgressSender {
+    pub fn job(&self) -> u64 {
+        self.job
+    }
+
+    pub fn stage(&self, index: usize, nodes: usize) {
+        self.send(ProgressUpdate::Stage { index, nodes });
+    }
+
+    pub fn comfy(&self, progress: ComfyProgress) {
+        self.send(ProgressUpdate::Comfy(progress));
+    }
+
+    fn send(&self, update: ProgressUpdate) {
+        // only fails if the app is shutting down
+        self.sender.send((self.entity, self.job, update)).ok();
+    }
+}
+
+pub fn update_progress(
+    mut channel: ResMut<ProgressChannel>,
+    mut query: Query<&mut WorkflowProgress>,
+) {
+    while let Ok((e, job, update)) = channel.receiver.try_recv() {
+        // progress can arrive after the generation finished or from a cancelled one
+        // while the next is running, ignore it
+        if let Ok(mut progress) = query.get_mut(e) {
+            if progress.job == job {
+                progress.apply(update);
+            }
+        }
     }
 }
 
-pub fn update_progress(time: Res<Time>, mut query: Query<(Entity, &mut WorkflowProgress)>) {
-    for (_e, mut progress) in query.iter_mut() {
-        progress.timer.tick(time.delta());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn executing(node: &str) -> ProgressUpdate {
+        ProgressUpdate::Comfy(ComfyProgress::Executing {
+            node: node.to_string(),
+            title: None,
+        })
+    }
+
+    #[test]
+    fn stage_math() {
+        let mut progress = WorkflowProgress::new(2);
+        assert_eq!(progress.value(), 0.0);
+        progress.apply(ProgressUpdate::Stage { index: 0, nodes: 4 });
+        progress.apply(ProgressUpdate::Comfy(ComfyProgress::Cached(vec![
+            "1".to_string(),
+            "2".to_string(),
+        ])));
+        assert_eq!(progress.stage_fraction(), 0.5);
+        progress.apply(executing("3"));
+        progress.apply(ProgressUpdate::Comfy(ComfyProgress::Step {
+            node: "3".to_string(),
+            value: 5,
+            max: 10,
+        }));
+        assert_eq!(progress.stage_fraction(), 0.625);
+        // the next node finishes the previous one
+        progress.apply(executing("4"));
+        assert_eq!(progress.done, ["1", "2", "3"]);
+        assert_eq!(progress.stage_fraction(), 0.75);
+        // half of the first of two stages
+        assert_eq!(progress.value(), 0.375);
+
+        progress.apply(ProgressUpdate::Stage { index: 1, nodes: 2 });
+        assert!(progress.done.is_empty());
+        assert_eq!(progress.node, None);
+        assert_eq!(progress.value(), 0.5);
+    }
+
+    #[test]
+    fn fraction_is_clamped() {
+        let mut progress = WorkflowProgress::new(0);
+        assert_eq!(progress.stages, 1);
+        // no nodes known yet
+        progress.apply(executing("1"));
+        assert_eq!(progress.stage_fraction(), 0.0);
+        progress.apply(ProgressUpdate::Stage { index: 0, nodes: 1 });
+        progress.apply(ProgressUpdate::Comfy(ComfyProgress::Cached(vec![
+            "1".to_string(),
+            "2".to_string(),
+            "2".to_string(),
+        ])));
+        assert_eq!(progress.done.len(), 2);
+        assert_eq!(progress.stage_fraction(), 1.0);
+    }
+
+    #[test]
+    fn updates_from_other_jobs_are_dropped() {
+        let mut world = World::new();
+        let mut channel = ProgressChannel::default();
+        let e = world.spawn_empty().id();
+        let old = channel.sender(e);
+        let current = channel.sender(e);
+        assert_ne!(old.job(), current.job());
+        world.entity_mut(e).insert(WorkflowProgress {
+            job: current.job(),
+            ..WorkflowProgress::new(1)
+        });
+        world.insert_resource(channel);
+
+        old.stage(0, 10);
+        current.stage(0, 4);
+        old.comfy(ComfyProgress::Cached(vec!["1".to_string()]));
+        world.run_system_once(update_progress).unwrap();
+
+        let progress = world.get::<WorkflowProgress>(e).unwrap();
+        assert_eq!(progress.nodes, 4);
+        assert!(progress.done.is_empty());
     }
 }
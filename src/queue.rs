@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+
+use crate::{InpaintJob, RetextureJob, Workflow, SHAPE_STAGE, TEXTURE_STAGE};
+
+/// A generation waiting for, or holding, a slot
+#[derive(Debug, Clone, Reflect)]
+pub struct GenerationJob {
+    pub entity: Entity,
+    /// prefab name when queued, for display
+    pub name: String,
+    /// workflow with its seed already picked
+    pub workflow: Workflow,
+    pub stage: Option<u8>,
+    /// higher runs first, same priority runs in order queued
+    pub priority: i32,
+    pub kind: JobKind,
+}
+
+/// What a job does to the prefab
+#[derive(Debug, Clone, Default, Reflect)]
+pub enum JobKind {
+    /// runs the prefab's workflow
+    #[default]
+    Generate,
+    /// regenerates the masked part of the image
+    Inpaint(InpaintJob),
+    /// paints the current model again from a reference image
+    Retexture(RetextureJob),
+}
+
+impl GenerationJob {
+    pub fn stage_label(&self) -> &'static str {
+        match self.kind {
+            JobKind::Inpaint(_) => return "Inpaint",
+            JobKind::Retexture(_) if self.stage == Some(TEXTURE_STAGE) => return "Texture",
+            JobKind::Retexture(_) => return "Retexture",
+            JobKind::Generate => {}
+        }
+        match (&self.workflow, self.stage) {
+            (Workflow::TextToModel { .. }, Some(0)) => "Image",
+            (Workflow::TextToModel { .. }, None) => "Full",
+            (_, Some(SHAPE_STAGE)) => "Shape",
+            (_, Some(TEXTURE_STAGE)) => "Texture",
+            (Workflow::ImageToModel { .. }, _) | (Workflow::MultiViewToModel { .. }, _) => {
+                "Model"
+            }
+            (Workflow::Custom { .. }, _) => "Custom",
+            _ => "Image",
+        }
+    }
+}
+
+/// Pending generations, started by `start_generations` while fewer than
+/// `max_in_flight` are running
+#[derive(Resource, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct GenerationQueue {
+    pub max_in_flight: usize,
+    pub pending: Vec<GenerationJob>,
+    pub running: Vec<GenerationJob>,
+}
+
+impl Default for GenerationQueue {
+    fn default() -> Self {
+        Self::new(default_max_in_flight())
+    }
+}
+
+pub fn default_max_in_flight() -> usize {
+    1
+}
+
+impl GenerationQueue {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            pending: Vec::new(),
+            running: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.running.is_empty()
+    }
+
+    pub fn is_running(&self, entity: Entity) -> bool {
+        self.running.iter().any(|j| j.entity == entity)
+    }
+
+    /// queues a job, a prefab only ever has one pending job so asking again replaces it
+    pub fn push(&mut self, mut job: GenerationJob) {
+        if let Some(existing) = self.pending.iter_mut().find(|j| j.entity == job.entity) {
+            job.priority = job.priority.max(existing.priority);
+            if job.priority == existing.priority {
+                *existing = job;
+                return;
+            }
+            self.remove(job.entity);
+        }
+        self.insert_by_priority(job);
+    }
+
+    fn insert_by_priority(&mut self, job: GenerationJob) {
+        let index = self
+            .pending
+            .iter()
+            .position(|j| j.priority < job.priority)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(index, job);
+    }
+
+    /// removes a pending job, returns true if there was one
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        let len = self.pending.len();
+        self.pending.retain(|j| j.entity != entity);
+        len != self.pending.len()
+    }
+
+    pub fn set_priority(&mut self, entity: Entity, priority: i32) {
+        if let Some(index) = self.pending.iter().position(|j| j.entity == entity) {
+            let mut job = self.pending.remove(index);
+            job.priority = priority;
+            self.insert_by_priority(job);
+        }
+    }
+
+    /// moves a pending job one place earlier, taking the priority of the job it passes
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.pending.len() {
+            self.pending[index].priority =
+                self.pending[index].priority.max(self.pending[index - 1].priority);
+            self.pending.swap(index, index - 1);
+        }
+    }
+
+    /// moves a pending job one place later, taking the priority of the job it passes
+    pub fn move_down(&mut self, index: usize) {
+        if index + 1 < self.pending.len() {
+            self.pending[index].priority =
+                self.pending[index].priority.min(self.pending[index + 1].priority);
+            self.pending.swap(index, index + 1);
+        }
+    }
+
+    /// takes the next job if there is a free slot, skipping prefabs that are already running
+    pub fn next_ready(&mut self) -> Option<GenerationJob> {
+        if self.running.len() >= self.max_in_flight.max(1) {
+            return None;
+        }
+        let index = self
+            .pending
+            .iter()
+            .position(|j| !self.running.iter().any(|r| r.entity == j.entity))?;
+        let job = self.pending.remove(index);
+        self.running.push(job.clone());
+        Some(job)
+    }
+
+    /// frees the slot held by this prefab, returns the job that held it
+    pub fn finish(&mut self, entity: Entity) -> Option<GenerationJob> {
+        let index = self.running.iter().position(|j| j.entity == entity)?;
+        Some(self.running.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(index: u32, name: &str, priority: i32) -> GenerationJob {
+        GenerationJob {
+            entity: Entity::from_raw(index),
+            name: name.to_string(),
+            workflow: Workflow::default(),
+            stage: None,
+            priority,
+            kind: JobKind::Generate,
+        }
+    }
+
+    fn names(queue: &GenerationQueue) -> Vec<&str> {
+        queue.pending.iter().map(|j| j.name.as_str()).collect()
+    }
+
+    #[test]
+    fn higher_priority_first_then_in_order() {
+        let mut queue = GenerationQueue::new(1);
+        queue.push(job(0, "a", 0));
+        queue.push(job(1, "b", 0));
+        queue.push(job(2, "c", 5));
+        queue.push(job(3, "d", 0));
+        assert_eq!(names(&queue), ["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn push_replaces_pending_job_of_same_prefab() {
+        let mut queue = GenerationQueue::new(1);
+        queue.push(job(0, "a", 0));
+        queue.push(job(1, "b", 0));
+        // same priority keeps its place
+        queue.push(job(0, "a2", 0));
+        assert_eq!(names(&queue), ["a2", "b"]);
+        // asking again never lowers the priority
+        queue.push(job(1, "b2", 3));
+        queue.push(job(1, "b3", 0));
+        assert_eq!(names(&queue), ["b3", "a2"]);
+        assert_eq!(queue.pending[0].priority, 3);
+    }
+
+    #[test]
+    fn set_priority_reorders() {
+        let mut queue = GenerationQueue::new(1);
+        queue.push(job(0, "a", 0));
+        queue.push(job(1, "b", 0));
+        queue.push(job(2, "c", 0));
+        queue.set_priority(Entity::from_raw(2), 1);
+        assert_eq!(names(&queue), ["c", "a", "b"]);
+        queue.set_priority(Entity::from_raw(2), -1);
+        assert_eq!(names(&queue), ["a", "b", "c"]);
+        // not pending, nothing to do
+        queue.set_priority(Entity::from_raw(9), 10);
+        assert_eq!(names(&queue), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn next_ready_respects_slots_and_running_prefabs() {
+        let mut queue = GenerationQueue::new(1);
+        queue.push(job(0, "a", 0));
+        let running = queue.next_ready().unwrap();
+        assert_eq!(running.name, "a");
+        queue.push(job(0, "a2", 0));
+        queue.push(job(1, "b", 0));
+        // no free slot
+        assert!(queue.next_ready().is_none());
+
+        queue.max_in_flight = 2;
+        // a is still running, so b goes first
+        assert_eq!(queue.next_ready().unwrap().name, "b");
+        assert_eq!(queue.finish(Entity::from_raw(0)).unwrap().name, "a");
+        assert_eq!(queue.next_ready().unwrap().name, "a2");
+        assert!(queue.finish(Entity::from_raw(5)).is_none());
+    }
+}
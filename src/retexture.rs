@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::global::GlobalEntropy;
+use rand::Rng;
+
+use crate::{
+    GenerationFailed, GenerationJob, GenerationQueue, JobKind, Prefab, WorkflowProgress,
+};
+
+/// Everything a retexture job needs besides the prefab's own settings
+#[derive(Debug, Clone, Default, Reflect)]
+pub struct RetextureJob {
+    /// image the new texture is painted from, relative to assets
+    pub reference: String,
+    pub seed: u64,
+}
+
+/// paints the prefab's current model again from a reference image, the shape is kept,
+/// None uses the prefab's own image
+#[derive(Event)]
+pub struct Retexture(pub Option<String>);
+
+pub fn on_retexture(
+    trigger: Trigger<Retexture>,
+    query: Query<&Prefab>,
+    mut queue: ResMut<GenerationQueue>,
+    mut rng: GlobalEntropy<WyRand>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let Ok(prefab) = query.get(e) else {
+        return;
+    };
+    let Some(model) = prefab.workflow.model() else {
+        warn!("{} has no model to retexture", prefab.name);
+        return;
+    };
+    // the result is written back over the model as glb
+    if !model.ends_with(".glb") {
+        warn!("Only glb models can be retextured, {} is {}", prefab.name, model);
+        return;
+    }
+    let Some(reference) = trigger
+        .0
+        .clone()
+        .or_else(|| prefab.workflow.image().cloned())
+    else {
+        warn!("No reference image to retexture {} from", prefab.name);
+        return;
+    };
+
+    commands.entity(e).remove::<GenerationFailed>();
+    if !queue.is_running(e) {
+        commands.entity(e).insert(WorkflowProgress::new(1));
+    }
+    queue.push(GenerationJob {
+        entity: e,
+        name: prefab.name.clone(),
+        workflow: prefab.workflow.clone(),
+        stage: None,
+        priority: 0,
+        kind: JobKind::Retexture(RetextureJob {
+            reference,
+            seed: rng.r#gen::<u64>(),
+        }),
+    });
+}
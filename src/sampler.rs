@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::WorkflowTemplate;
+
+/// (label, width, height), multiples of 8 around the same pixel count
+pub const SIZE_PRESETS: [(&str, u32, u32); 7] = [
+    ("1:1", 768, 768),
+    ("4:3", 896, 672),
+    ("3:4", 672, 896),
+    ("3:2", 912, 608),
+    ("2:3", 608, 912),
+    ("16:9", 1024, 576),
+    ("9:16", 576, 1024),
+];
+
+/// KSampler and latent settings, defaults match ref_image_gen.workflow.json
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplerSettings {
+    /// None keeps the workflow's negative prompt
+    pub negative: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub steps: u32,
+    pub cfg: f32,
+    pub sampler: String,
+    pub scheduler: String,
+    /// images per run, all but the first become variants
+    pub batch_size: u32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            negative: None,
+            width: 768,
+            height: 768,
+            steps: 30,
+            cfg: 7.0,
+            sampler: "dpmpp_2m".to_string(),
+            scheduler: "karras".to_string(),
+            batch_size: 1,
+        }
+    }
+}
+
+/// Start from the prefab's current image instead of an empty latent
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Img2ImgSettings {
+    pub enabled: bool,
+    /// 0 keeps the image, 1 ignores it
+    pub denoise: f32,
+}
+
+impl Default for Img2ImgSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            denoise: 0.5,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn apply(&self, template: &WorkflowTemplate, workflow: &mut Value) {
+        if let Some(negative) = &self.negative {
+            template.set(workflow, "negative", negative);
+        }
+        template.set(workflow, "width", self.width);
+        template.set(workflow, "height", self.height);
+        template.set(workflow, "steps", self.steps);
+        template.set(workflow, "cfg", self.cfg);
+        template.set(workflow, "sampler", &self.sampler);
+        template.set(workflow, "scheduler", &self.scheduler);
+        template.set(workflow, "batch_size", self.batch_size.max(1));
+    }
+
+    /// width / height
+    pub fn aspect(&self) -> f32 {
+        self.width.max(1) as f32 / self.height.max(1) as f32
+    }
+
+    pub fn preset(&self) -> Option<&'static str> {
+        SIZE_PRESETS
+            .iter()
+            .find(|(_, w, h)| *w == self.width && *h == self.height)
+            .map(|(label, _, _)| *label)
+    }
+}
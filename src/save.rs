@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::{Prefab, PrefabConfig, RefConfig};
+use crate::{ComfyClient, GenerationQueue, Prefab, PrefabConfig, RefConfig, CONFIG_VERSION};
 
 /// A timer resource used to save the game state periodically.
 #[derive(Debug, Resource, Deref, DerefMut, Reflect)]
@@ -30,9 +30,16 @@ pub fn save_on_exit(mut commands: Commands) {
 }
 
 // save the current state of the world
-pub fn save(query: Query<(&Transform, &Prefab)>) {
+pub fn save(
+    query: Query<(&Transform, &Prefab)>,
+    comfy: Res<ComfyClient>,
+    queue: Res<GenerationQueue>,
+) {
     info!("Saving...");
     let mut config = RefConfig {
+        version: CONFIG_VERSION,
+        comfy: comfy.clone(),
+        max_in_flight: queue.max_in_flight,
         prefabs: Vec::new(),
     };
 
@@ -0,0 +1,676 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadedFolder},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::ComfyError;
+
+/// workflow used to generate images
+pub const IMAGE_TEMPLATE: &str = "ref_image_gen";
+/// workflow used to refine an existing image
+pub const IMG2IMG_TEMPLATE: &str = "ref_img2img";
+/// workflow used to regenerate a masked part of an image
+pub const INPAINT_TEMPLATE: &str = "ref_inpaint";
+/// workflow used to generate models from an image
+pub const MODEL_TEMPLATE: &str = "ref_3d_gen";
+/// workflow used to generate models from several views
+pub const MULTIVIEW_TEMPLATE: &str = "ref_3d_mv_gen";
+/// workflow used to paint a new texture on an existing model
+pub const RETEXTURE_TEMPLATE: &str = "ref_retexture";
+
+const WORKFLOW_DIR: &str = "workflows";
+const MANIFEST_EXTENSION: &str = "workflow.ron";
+/// not plain json, so other json files under assets are left alone
+const WORKFLOW_EXTENSION: &str = "workflow.json";
+
+pub struct WorkflowTemplatePlugin;
+
+impl Plugin for WorkflowTemplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<WorkflowJson>()
+            .init_asset::<WorkflowTemplate>()
+            .init_asset_loader::<WorkflowJsonLoader>()
+            .init_asset_loader::<WorkflowTemplateLoader>()
+            .init_resource::<WorkflowTemplates>()
+            .add_systems(Startup, load_workflow_templates)
+            .add_systems(Update, update_workflow_templates);
+    }
+}
+
+/// Sidecar `<name>.workflow.ron` next to a ComfyUI api export, names the inputs we patch
+/// so re-exporting a workflow only means fixing the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowManifest {
+    /// api format json, relative to the manifest, ex "ref_image_gen.workflow.json"
+    pub workflow: String,
+    /// named inputs, ex "seed", "prompt", "negative", "image", "save_name"
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamTarget>,
+    /// nodes whose results we download, ex "image", "model"
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputTarget>,
+}
+
+/// A node input, found by node id or by node title
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParamTarget {
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    pub input: String,
+    /// how custom workflows show it, hidden if not set
+    #[serde(default)]
+    pub kind: ParamKind,
+    /// param removed when this image is left unset, ex the link from an optional image's
+    /// loader so it never runs
+    #[serde(default)]
+    pub optional_link: Option<String>,
+}
+
+/// Param types custom workflows can edit, the default value comes from the workflow json
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ParamKind {
+    /// set by sly_ref, ex save_name
+    #[default]
+    Hidden,
+    String {
+        #[serde(default)]
+        multiline: bool,
+    },
+    Int {
+        min: i64,
+        max: i64,
+    },
+    Float {
+        min: f64,
+        max: f64,
+    },
+    Bool,
+    Enum(Vec<String>),
+    /// file in a ComfyUI model folder, ex "checkpoints", "loras"
+    Model(String),
+    /// seed that can be randomized each generation
+    Seed,
+    /// image of a prefab on the board, uploaded before queuing
+    Image,
+}
+
+/// Value of a custom workflow param
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub enum ParamValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Seed { seed: u64, random: bool },
+    /// asset path of the image
+    Image(Option<String>),
+}
+
+impl ParamValue {
+    /// value from the workflow json, falls back to an empty value of the right kind
+    pub fn from_json(kind: &ParamKind, value: &Value) -> Option<Self> {
+        Some(match kind {
+            ParamKind::Hidden => return None,
+            ParamKind::String { .. } | ParamKind::Enum(_) | ParamKind::Model(_) => {
+                ParamValue::String(value.as_str().unwrap_or_default().to_string())
+            }
+            ParamKind::Int { .. } => ParamValue::Int(value.as_i64().unwrap_or_default()),
+            ParamKind::Float { .. } => ParamValue::Float(value.as_f64().unwrap_or_default()),
+            ParamKind::Bool => ParamValue::Bool(value.as_bool().unwrap_or_default()),
+            ParamKind::Seed => ParamValue::Seed {
+                seed: value.as_u64().unwrap_or_default(),
+                random: false,
+            },
+            ParamKind::Image => ParamValue::Image(None),
+        })
+    }
+
+    /// json to set on the node, images need uploading first so are left out
+    pub fn to_json(&self) -> Option<Value> {
+        match self {
+            ParamValue::String(s) => Some(json!(s)),
+            ParamValue::Int(i) => Some(json!(i)),
+            ParamValue::Float(f) => Some(json!(f)),
+            ParamValue::Bool(b) => Some(json!(b)),
+            ParamValue::Seed { seed, .. } => Some(json!(seed)),
+            ParamValue::Image(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputTarget {
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    pub kind: OutputKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Reflect)]
+pub enum OutputKind {
+    Image,
+    Model,
+}
+
+/// Raw ComfyUI api format workflow
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct WorkflowJson(pub Value);
+
+/// A workflow with its manifest resolved to node ids
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct WorkflowTemplate {
+    pub name: String,
+    pub manifest: WorkflowManifest,
+    pub workflow: Value,
+    /// param name -> (node id, input)
+    params: HashMap<String, (String, String)>,
+    /// output name -> (node id, kind)
+    outputs: HashMap<String, (String, OutputKind)>,
+}
+
+impl WorkflowTemplate {
+    /// resolves every param and output of the manifest to a node in the workflow
+    pub fn new(name: String, manifest: WorkflowManifest, workflow: Value) -> Result<Self, String> {
+        let mut params = HashMap::new();
+        for (param, target) in manifest.params.iter() {
+            let node = find_node(&workflow, &target.node, &target.title)
+                .ok_or_else(|| format!("{}: node for param '{}' not found", name, param))?;
+            params.insert(param.clone(), (node, target.input.clone()));
+            if let Some(link) = &target.optional_link {
+                if !manifest.params.contains_key(link) {
+                    return Err(format!(
+                        "{}: optional link '{}' of param '{}' is not a param",
+                        name, link, param
+                    ));
+                }
+            }
+        }
+        let mut outputs = HashMap::new();
+        for (output, target) in manifest.outputs.iter() {
+            let node = find_node(&workflow, &target.node, &target.title)
+                .ok_or_else(|| format!("{}: node for output '{}' not found", name, output))?;
+            outputs.insert(output.clone(), (node, target.kind));
+        }
+
+        Ok(WorkflowTemplate {
+            name,
+            manifest,
+            workflow,
+            params,
+            outputs,
+        })
+    }
+
+    /// fresh copy of the workflow to patch and queue
+    pub fn instantiate(&self) -> Value {
+        self.workflow.clone()
+    }
+
+    pub fn has_param(&self, param: &str) -> bool {
+        self.params.contains_key(param)
+    }
+
+    /// sets a named param, returns false if the manifest doesnt define it
+    pub fn set(&self, workflow: &mut Value, param: &str, value: impl Serialize) -> bool {
+        let Some((node, input)) = self.params.get(param) else {
+            return false;
+        };
+        workflow[node.as_str()]["inputs"][input.as_str()] = json!(value);
+        true
+    }
+
+    /// current value of a named param's input, links included
+    pub fn get<'a>(&self, workflow: &'a Value, param: &str) -> Option<&'a Value> {
+        let (node, input) = self.params.get(param)?;
+        workflow[node.as_str()]["inputs"].get(input.as_str())
+    }
+
+    /// removes a named param's input, ex to leave out an optional link,
+    /// returns false if the manifest doesnt define it
+    pub fn unset(&self, workflow: &mut Value, param: &str) -> bool {
+        let Some((node, input)) = self.params.get(param) else {
+            return false;
+        };
+        if let Some(inputs) = workflow[node.as_str()]["inputs"].as_object_mut() {
+            inputs.remove(input);
+        }
+        true
+    }
+
+    /// removes the param's `optional_link`, returns false if it has none
+    pub fn unset_optional(&self, workflow: &mut Value, param: &str) -> bool {
+        match self
+            .manifest
+            .params
+            .get(param)
+            .and_then(|target| target.optional_link.as_ref())
+        {
+            Some(link) => self.unset(workflow, link),
+            None => false,
+        }
+    }
+
+    /// node id a param is set on
+    pub fn param_node(&self, param: &str) -> Option<&str> {
+        self.params.get(param).map(|(node, _)| node.as_str())
+    }
+
+    /// true for inputs always replaced before queuing, their saved value doesnt matter
+    pub fn overwrites(&self, node: &str, input: &str) -> bool {
+        self.manifest.params.iter().any(|(name, target)| {
+            matches!(target.kind, ParamKind::Hidden | ParamKind::Image)
+                && self
+                    .params
+                    .get(name)
+                    .is_some_and(|(n, i)| n == node && i == input)
+        })
+    }
+
+    /// node id of a named output
+    pub fn output(&self, name: &str) -> Option<&str> {
+        self.outputs.get(name).map(|(node, _)| node.as_str())
+    }
+
+    /// node ids and kinds of every output, sorted by name so "model" comes before "shape"
+    pub fn outputs(&self) -> impl Iterator<Item = (&str, OutputKind)> {
+        let mut outputs = self.outputs.iter().collect::<Vec<_>>();
+        outputs.sort_by_key(|(name, _)| *name);
+        outputs
+            .into_iter()
+            .map(|(_, (node, kind))| (node.as_str(), *kind))
+    }
+
+    /// params shown to the user, sorted by name
+    pub fn visible_params(&self) -> impl Iterator<Item = (&String, &ParamKind)> {
+        self.manifest
+            .params
+            .iter()
+            .filter(|(_, target)| target.kind != ParamKind::Hidden)
+            .map(|(name, target)| (name, &target.kind))
+    }
+
+    /// current value of a visible param in the workflow json
+    pub fn default_value(&self, param: &str) -> Option<ParamValue> {
+        let target = self.manifest.params.get(param)?;
+        let (node, input) = self.params.get(param)?;
+        ParamValue::from_json(
+            &target.kind,
+            &self.workflow[node.as_str()]["inputs"][input.as_str()],
+        )
+    }
+
+    /// every visible param with the value saved in the workflow
+    pub fn default_params(&self) -> BTreeMap<String, ParamValue> {
+        self.visible_params()
+            .filter_map(|(name, _)| Some((name.clone(), self.default_value(name)?)))
+            .collect()
+    }
+}
+
+/// drops every node the `keep` nodes dont depend on, so only they run
+pub fn prune_workflow(workflow: &mut Value, keep: &[&str]) {
+    let Some(nodes) = workflow.as_object_mut() else {
+        return;
+    };
+    let mut needed = HashSet::new();
+    let mut stack = keep.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        if !needed.insert(id.clone()) {
+            continue;
+        }
+        let Some(inputs) = nodes.get(&id).and_then(|n| n["inputs"].as_object()) else {
+            continue;
+        };
+        // links are [node id, output index]
+        for value in inputs.values() {
+            if let Some(link) = value.as_array().and_then(|l| l.first()?.as_str()) {
+                stack.push(link.to_string());
+            }
+        }
+    }
+    nodes.retain(|id, _| needed.contains(id));
+}
+
+fn find_node(workflow: &Value, node: &Option<String>, title: &Option<String>) -> Option<String> {
+    let nodes = workflow.as_object()?;
+    if let Some(node) = node {
+        return nodes.contains_key(node).then(|| node.clone());
+    }
+    let title = title.as_ref()?;
+    nodes
+        .iter()
+        .find(|(_, n)| n["_meta"]["title"].as_str() == Some(title.as_str()))
+        .map(|(id, _)| id.clone())
+}
+
+#[derive(Default)]
+pub struct WorkflowJsonLoader;
+
+impl AssetLoader for WorkflowJsonLoader {
+    type Asset = WorkflowJson;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(WorkflowJson(serde_json::from_slice(&bytes)?))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[WORKFLOW_EXTENSION]
+    }
+}
+
+#[derive(Default)]
+pub struct WorkflowTemplateLoader;
+
+impl AssetLoader for WorkflowTemplateLoader {
+    type Asset = WorkflowTemplate;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let manifest: WorkflowManifest = ron::de::from_bytes(&bytes)?;
+
+        let name = template_name(load_context.path());
+        // loading through the context makes the json a dependency, so editing it hot reloads us
+        let json_path = load_context
+            .path()
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&manifest.workflow);
+        let workflow = load_context
+            .loader()
+            .immediate()
+            .load::<WorkflowJson>(json_path)
+            .await?
+            .take()
+            .0;
+        Ok(WorkflowTemplate::new(name, manifest, workflow)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[MANIFEST_EXTENSION]
+    }
+}
+
+/// "workflows/ref_image_gen.workflow.ron" -> "ref_image_gen"
+fn template_name(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    file_name
+        .strip_suffix(&format!(".{}", MANIFEST_EXTENSION))
+        .unwrap_or(file_name)
+        .to_string()
+}
+
+/// Every template found in `assets/workflows`, by name
+#[derive(Resource, Default)]
+pub struct WorkflowTemplates(pub HashMap<String, Handle<WorkflowTemplate>>);
+
+impl WorkflowTemplates {
+    pub fn get<'a>(
+        &self,
+        name: &str,
+        assets: &'a Assets<WorkflowTemplate>,
+    ) -> Result<&'a WorkflowTemplate, ComfyError> {
+        self.0
+            .get(name)
+            .and_then(|handle| assets.get(handle))
+            .ok_or_else(|| ComfyError::MissingTemplate(name.to_string()))
+    }
+}
+
+/// Keeps every file in `assets/workflows` loaded, the folder reloads when a file is added
+#[derive(Resource)]
+struct WorkflowFolder {
+    _handle: Handle<LoadedFolder>,
+}
+
+fn load_workflow_templates(asset_server: Res<AssetServer>, mut commands: Commands) {
+    commands.insert_resource(WorkflowFolder {
+        _handle: asset_server.load_folder(WORKFLOW_DIR),
+    });
+}
+
+/// picks up templates as they load, so a manifest added while running shows up
+fn update_workflow_templates(
+    mut events: EventReader<AssetEvent<WorkflowTemplate>>,
+    asset_server: Res<AssetServer>,
+    assets: Res<Assets<WorkflowTemplate>>,
+    mut templates: ResMut<WorkflowTemplates>,
+) {
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } => {
+                let (Some(template), Some(handle)) =
+                    (assets.get(*id), asset_server.get_id_handle(*id))
+                else {
+                    continue;
+                };
+                info!("Loaded workflow template {}", template.name);
+                templates.0.insert(template.name.clone(), handle);
+            }
+            AssetEvent::Modified { id } => {
+                if let Some(template) = assets.get(*id) {
+                    info!("Reloaded workflow template {}", template.name);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow() -> Value {
+        json!({
+            "3": {
+                "class_type": "KSampler",
+                "inputs": { "seed": 5, "steps": 20, "model": ["4", 0], "positive": ["6", 0] },
+                "_meta": { "title": "KSampler" }
+            },
+            "4": {
+                "class_type": "CheckpointLoaderSimple",
+                "inputs": { "ckpt_name": "sdxl.safetensors" },
+                "_meta": { "title": "Load Checkpoint" }
+            },
+            "6": {
+                "class_type": "CLIPTextEncode",
+                "inputs": { "text": "a car", "clip": ["4", 1] },
+                "_meta": { "title": "Positive" }
+            },
+            "9": {
+                "class_type": "SaveImage",
+                "inputs": { "filename_prefix": "ComfyUI", "images": ["3", 0] },
+                "_meta": { "title": "Save Image" }
+            },
+            "20": {
+                "class_type": "PreviewImage",
+                "inputs": { "images": ["3", 0] },
+                "_meta": { "title": "Preview" }
+            }
+        })
+    }
+
+    fn template() -> WorkflowTemplate {
+        let manifest: WorkflowManifest = ron::from_str(
+            r#"(
+                workflow: "test.workflow.json",
+                params: {
+                    "seed": (title: Some("KSampler"), input: "seed", kind: Seed),
+                    "steps": (node: Some("3"), input: "steps", kind: Int(min: 1, max: 100)),
+                    "prompt": (title: Some("Positive"), input: "text"),
+                    "save_name": (title: Some("Save Image"), input: "filename_prefix"),
+                },
+                outputs: {
+                    "image": (title: Some("Save Image"), kind: Image),
+                    "preview": (node: Some("20"), kind: Image),
+                },
+            )"#,
+        )
+        .unwrap();
+        WorkflowTemplate::new("test".to_string(), manifest, workflow()).unwrap()
+    }
+
+    #[test]
+    fn resolves_params_and_outputs_by_id_or_title() {
+        let template = template();
+        assert_eq!(template.param_node("seed"), Some("3"));
+        assert_eq!(template.param_node("steps"), Some("3"));
+        assert_eq!(template.param_node("prompt"), Some("6"));
+        assert_eq!(template.output("image"), Some("9"));
+        // sorted by name
+        let outputs = template.outputs().map(|(node, _)| node).collect::<Vec<_>>();
+        assert_eq!(outputs, ["9", "20"]);
+    }
+
+    #[test]
+    fn missing_node_fails() {
+        let manifest: WorkflowManifest = ron::from_str(
+            r#"(workflow: "x", params: { "seed": (title: Some("Nope"), input: "seed") })"#,
+        )
+        .unwrap();
+        let err = WorkflowTemplate::new("test".to_string(), manifest, workflow()).unwrap_err();
+        assert!(err.contains("'seed'"));
+    }
+
+    #[test]
+    fn set_get_and_unset() {
+        let template = template();
+        let mut workflow = template.instantiate();
+        assert!(template.set(&mut workflow, "seed", 42u64));
+        assert!(template.set(&mut workflow, "prompt", "a red car"));
+        assert_eq!(template.get(&workflow, "seed"), Some(&json!(42)));
+        assert_eq!(template.get(&workflow, "prompt"), Some(&json!("a red car")));
+        // the template itself is untouched
+        assert_eq!(template.get(&template.workflow, "seed"), Some(&json!(5)));
+
+        assert!(!template.set(&mut workflow, "negative", "blurry"));
+        assert!(!template.has_param("negative"));
+        assert_eq!(template.get(&workflow, "negative"), None);
+
+        assert!(template.unset(&mut workflow, "prompt"));
+        assert_eq!(template.get(&workflow, "prompt"), None);
+        assert!(!template.unset(&mut workflow, "negative"));
+    }
+
+    #[test]
+    fn optional_link_unsets_its_param() {
+        let manifest: WorkflowManifest = ron::from_str(
+            r#"(
+                workflow: "x",
+                params: {
+                    "image": (title: Some("Preview"), input: "images", kind: Image, optional_link: Some("link")),
+                    "link": (node: Some("9"), input: "images"),
+                    "prompt": (title: Some("Positive"), input: "text"),
+                },
+            )"#,
+        )
+        .unwrap();
+        let template = WorkflowTemplate::new("test".to_string(), manifest, workflow()).unwrap();
+        let mut workflow = template.instantiate();
+        assert!(template.unset_optional(&mut workflow, "image"));
+        assert_eq!(template.get(&workflow, "link"), None);
+        assert_eq!(template.get(&workflow, "image"), Some(&json!(["3", 0])));
+        // params without a link are left alone
+        assert!(!template.unset_optional(&mut workflow, "prompt"));
+        assert!(template.get(&workflow, "prompt").is_some());
+
+        let manifest: WorkflowManifest = ron::from_str(
+            r#"(workflow: "x", params: { "image": (node: Some("20"), input: "images", optional_link: Some("nope")) })"#,
+        )
+        .unwrap();
+        let err = WorkflowTemplate::new("test".to_string(), manifest, workflow()).unwrap_err();
+        assert!(err.contains("nope"));
+    }
+
+    #[test]
+    fn default_params_only_visible() {
+        let template = template();
+        let params = template.default_params();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params["steps"], ParamValue::Int(20));
+        assert_eq!(
+            params["seed"],
+            ParamValue::Seed {
+                seed: 5,
+                random: false
+            }
+        );
+        assert!(template.overwrites("9", "filename_prefix"));
+        assert!(!template.overwrites("3", "steps"));
+    }
+
+    #[test]
+    fn prune_keeps_only_dependencies() {
+        let mut pruned = workflow();
+        prune_workflow(&mut pruned, &["3"]);
+        let mut kept = pruned.as_object().unwrap().keys().collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, ["3", "4", "6"]);
+
+        // missing nodes are ignored
+        let mut pruned = workflow();
+        prune_workflow(&mut pruned, &["9", "99"]);
+        assert_eq!(pruned.as_object().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn template_names() {
+        assert_eq!(
+            template_name(Path::new("workflows/ref_image_gen.workflow.ron")),
+            "ref_image_gen"
+        );
+        assert_eq!(template_name(Path::new("other.ron")), "other.ron");
+    }
+
+    /// every shipped manifest has to resolve against its json
+    #[test]
+    fn bundled_templates_resolve() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(WORKFLOW_DIR);
+        let mut count = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with(MANIFEST_EXTENSION) {
+                continue;
+            }
+            let manifest: WorkflowManifest =
+                ron::de::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+            let json = std::fs::read(dir.join(&manifest.workflow)).unwrap();
+            let workflow = serde_json::from_slice(&json).unwrap();
+            if let Err(err) = WorkflowTemplate::new(template_name(&path), manifest, workflow) {
+                panic!("{}", err);
+            }
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+}
@@ -5,12 +5,26 @@ use bevy::{
     window::PrimaryWindow,
 };
 use bevy_inspector_egui::{
-    bevy_egui::EguiContext,
+    bevy_egui::{EguiContext, EguiUserTextures},
     egui::{self},
 };
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 use strum::IntoEnumIterator;
 
-use crate::{Generate, Prefab, Rename, Save, Selected, SpawnPrefab, Workflow};
+use crate::{
+    fetch_models, fetch_object_info, Cancel, ClearMask, ComfyClient, ComfyModels, ComfyScheme,
+    DiscardVariants, Generate, GenerationFailed, GenerationQueue, Img2ImgSettings, ImportDialog,
+    ImportFiles, Inpaint, KeepVariants, LoraSettings, MaskPainter, MeshSettings, MetadataInspector,
+    ModelSettings, ModelViews, ObjectInfo, OutputKind, ParamKind, ParamValue, Prefab, Rename,
+    Retexture, Retry, Revert, SamplerSettings, Save, Selected, SpawnPrefab, ToggleMask, Variation,
+    Version, Workflow, WorkflowDiagnostics, WorkflowProgress, WorkflowTemplate, WorkflowTemplates,
+    CHECKPOINTS, IMAGE_TEMPLATE, LORAS, SHAPE_STAGE, SIZE_PRESETS, TEXTURE_SIZES, TEXTURE_STAGE,
+    VAES,
+};
+use bevy_health_bar3d::prelude::Percentage;
+use bevy_tokio_tasks::TokioTasksRuntime;
 
 const NORMAL_BUTTON: Color = Color::Srgba(tailwind::SLATE_500);
 const NORMAL_BUTTON_BORDER: Color = Color::Srgba(tailwind::SLATE_600);
@@ -136,6 +150,20 @@ pub fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
                         commands.send_event(SpawnPrefab);
                     },
                 );
+            parent
+                .spawn((
+                    Name::new("Quick Import Button"),
+                    QuickButton,
+                    children!((
+                        QuickButtonInner,
+                        ImageNode::new(asset_server.load("textures/icon/white/import.png")),
+                    )),
+                ))
+                .observe(
+                    |_trigger: Trigger<Pointer<Click>>, mut dialog: ResMut<ImportDialog>| {
+                        dialog.open = true;
+                    },
+                );
             parent
                 .spawn((
                     Name::new("Quick Save Button"),
@@ -175,10 +203,48 @@ pub fn ui_select(world: &mut World) {
         .expect("No EguiContext found")
         .clone();
 
-    let mut system_state: SystemState<(Commands, Query<(Entity, &mut Prefab), With<Selected>>)> =
-        SystemState::new(world);
+    // (name, image) of everything on the board, for image params
+    let mut board = world
+        .query::<&Prefab>()
+        .iter(world)
+        .filter_map(|p| Some((p.name.clone(), p.workflow.image()?.clone())))
+        .collect::<Vec<_>>();
+    board.sort();
+
+    let mut system_state: SystemState<(
+        Commands,
+        Query<
+            (
+                Entity,
+                &mut Prefab,
+                Option<&WorkflowProgress>,
+                Option<&GenerationFailed>,
+                Option<&mut MaskPainter>,
+            ),
+            With<Selected>,
+        >,
+        Res<WorkflowTemplates>,
+        Res<Assets<WorkflowTemplate>>,
+        Res<WorkflowDiagnostics>,
+        Res<ComfyModels>,
+        Res<ObjectInfo>,
+        ResMut<EguiUserTextures>,
+        Res<AssetServer>,
+        ResMut<MetadataInspector>,
+    )> = SystemState::new(world);
 
-    let (mut cmd, mut query) = system_state.get_mut(world);
+    let (
+        mut cmd,
+        mut query,
+        templates,
+        template_assets,
+        diagnostics,
+        comfy_models,
+        object_info,
+        mut textures,
+        asset_server,
+        mut inspector,
+    ) = system_state.get_mut(world);
 
     egui::Window::new("Select").show(egui_context.get_mut(), |ui| {
         egui::ScrollArea::both().show(ui, |ui| {
@@ -190,7 +256,7 @@ pub fn ui_select(world: &mut World) {
             //         .name = prefab.name.clone();
             // }
             //bevy_inspector_egui::bevy_inspector::ui_for_entities_filtered(world, ui, false, &Filter::<With<Prefab>>::all());
-            for (e, mut p) in query.iter_mut() {
+            for (e, mut p, progress, failed, mut mask) in query.iter_mut() {
                 let id = egui::Id::new("prefab ui").with(e);
                 let mut changed = false;
                 egui::Grid::new(id)
@@ -207,106 +273,40 @@ pub fn ui_select(world: &mut World) {
                         ui.end_row();
 
                         ui.label("Workflow");
-                        let mut workflow_copy = p.workflow.clone();
-                        let mut workflow_changed = false;
+                        let mut choices = Workflow::iter()
+                            .filter(|w| !matches!(w, Workflow::Custom { .. }))
+                            .collect::<Vec<_>>();
+                        let mut names = templates.0.keys().collect::<Vec<_>>();
+                        names.sort();
+                        for name in names {
+                            if let Ok(template) = templates.get(name, &template_assets) {
+                                choices.push(Workflow::Custom {
+                                    template: name.clone(),
+                                    params: template.default_params(),
+                                    outputs: default(),
+                                });
+                            }
+                        }
+                        let mut new_workflow = None;
                         egui::ComboBox::from_id_salt(id)
-                            .selected_text(format!("{}", workflow_copy))
+                            .selected_text(format!("{}", p.workflow))
                             .show_ui(ui, |ui| {
-                                for variant in Workflow::iter() {
-                                    workflow_changed |= ui
-                                        .selectable_value(
-                                            &mut workflow_copy,
-                                            variant.clone(),
-                                            format!("{}", &variant),
-                                        )
-                                        .changed();
+                                for choice in choices {
+                                    let selected = p.workflow.same_kind(&choice);
+                                    if ui
+                                        .selectable_label(selected, format!("{}", choice))
+                                        .clicked()
+                                        && !selected
+                                    {
+                                        new_workflow = Some(choice);
+                                    }
                                 }
                             });
 
-                        // handles keeping useful data when changing workflow types
-                        if workflow_changed {
+                        // keeps useful data when changing workflow types
+                        if let Some(to) = new_workflow {
                             changed = true;
-                            match (workflow_copy, &p.workflow) {
-                                (
-                                    Workflow::StaticImage { .. },
-                                    Workflow::TextToImage { image, .. },
-                                ) => {
-                                    p.workflow = Workflow::StaticImage {
-                                        image: image.clone(),
-                                    };
-                                }
-                                (Workflow::StaticImage { .. }, Workflow::StaticImage { .. }) => {
-                                    unreachable!()
-                                }
-                                (
-                                    Workflow::StaticImage { .. },
-                                    Workflow::TextToModel { image, .. },
-                                ) => {
-                                    p.workflow = Workflow::StaticImage {
-                                        image: image.clone(),
-                                    };
-                                }
-                                (Workflow::TextToImage { .. }, Workflow::StaticImage { image }) => {
-                                    p.workflow = Workflow::TextToImage {
-                                        seed: 0,
-                                        seed_random: false,
-                                        prompt: "".to_string(),
-                                        image: image.clone(),
-                                    };
-                                }
-                                (
-                                    Workflow::TextToImage { .. },
-                                    Workflow::TextToModel {
-                                        image,
-                                        seed,
-                                        seed_random,
-                                        prompt,
-                                        ..
-                                    },
-                                ) => {
-                                    p.workflow = Workflow::TextToImage {
-                                        image: image.clone(),
-                                        seed: *seed,
-                                        seed_random: *seed_random,
-                                        prompt: prompt.clone(),
-                                    };
-                                }
-                                (Workflow::TextToImage { .. }, Workflow::TextToImage { .. }) => {
-                                    unreachable!()
-                                }
-                                (Workflow::TextToModel { .. }, Workflow::StaticImage { image }) => {
-                                    p.workflow = Workflow::TextToModel {
-                                        seed: 0,
-                                        seed_random: false,
-                                        prompt: "".to_string(),
-                                        image: image.clone(),
-                                        num_faces: 50000,
-                                        model: None,
-                                    };
-                                }
-                                (
-                                    Workflow::TextToModel { .. },
-                                    Workflow::TextToImage {
-                                        image,
-                                        seed,
-                                        seed_random,
-                                        prompt,
-                                    },
-                                ) => {
-                                    p.workflow = Workflow::TextToModel {
-                                        seed: *seed,
-                                        seed_random: *seed_random,
-                                        num_faces: 50000,
-                                        prompt: prompt.clone(),
-                                        image: image.clone(),
-                                        model: None,
-                                    };
-                                }
-
-                                (Workflow::TextToModel { .. }, Workflow::TextToModel { .. }) => {
-                                    unreachable!()
-                                }
-                            }
+                            p.workflow = p.workflow.convert(&to);
                         }
                         ui.end_row();
 
@@ -316,15 +316,28 @@ pub fn ui_select(world: &mut World) {
                                 image_widget(ui, image);
                                 enable_generate = false;
                             }
+                            Workflow::StaticModel { model } => {
+                                model_widget(ui, model);
+                                enable_generate = false;
+                            }
                             Workflow::TextToImage {
                                 image,
                                 prompt,
                                 seed,
                                 seed_random,
+                                models,
+                                sampler,
+                                img2img,
                             } => {
                                 changed |= prompt_widget(ui, prompt);
                                 image_widget(ui, image);
-                                seed_wigit(ui, seed, seed_random);
+                                changed |= img2img_widget(ui, img2img, image.is_some());
+                                seed_wigit(ui, id.with("seed"), seed, seed_random);
+                                let template =
+                                    templates.get(IMAGE_TEMPLATE, &template_assets).ok();
+                                changed |= sampler_widget(ui, id, sampler, &object_info, template);
+                                changed |=
+                                    models_widget(ui, id, models, &comfy_models, template);
                             }
                             Workflow::TextToModel {
                                 prompt,
@@ -333,22 +346,22 @@ pub fn ui_select(world: &mut World) {
                                 seed,
                                 seed_random,
                                 num_faces,
+                                models,
+                                sampler,
+                                mesh,
                             } => {
                                 changed |= prompt_widget(ui, prompt);
                                 image_widget(ui, image);
                                 model_widget(ui, model);
-                                changed |= seed_wigit(ui, seed, seed_random);
+                                changed |= seed_wigit(ui, id.with("seed"), seed, seed_random);
+                                let template =
+                                    templates.get(IMAGE_TEMPLATE, &template_assets).ok();
+                                changed |= sampler_widget(ui, id, sampler, &object_info, template);
+                                changed |=
+                                    models_widget(ui, id, models, &comfy_models, template);
 
-                                ui.label("Faces");
-                                changed |= ui
-                                    .add(
-                                        egui::Slider::new(num_faces, 0..=u32::MAX)
-                                            .text("Faces")
-                                            .step_by(1000.)
-                                            .clamping(egui::SliderClamping::Always),
-                                    )
-                                    .changed();
-                                ui.end_row();
+                                changed |= faces_widget(ui, num_faces);
+                                changed |= mesh_widget(ui, id, mesh);
 
                                 ui.label("");
                                 if ui
@@ -363,21 +376,108 @@ pub fn ui_select(world: &mut World) {
                                 }
                                 ui.end_row();
 
-                                ui.label("");
-                                if ui
-                                    .add_enabled(
-                                        enable_generate,
-                                        egui::Button::new("Generate Model")
-                                            .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
-                                    )
-                                    .clicked()
-                                {
-                                    cmd.trigger_targets(Generate(Some(1)), e);
+                                if let Some(stage) = model_stage_buttons(ui, enable_generate, mesh) {
+                                    cmd.trigger_targets(Generate(Some(stage)), e);
                                 }
-                                ui.end_row();
+                            }
+                            Workflow::ImageToModel {
+                                image,
+                                model,
+                                seed,
+                                seed_random,
+                                num_faces,
+                                mesh,
+                            } => {
+                                image_widget(ui, image);
+                                model_widget(ui, model);
+                                changed |= seed_wigit(ui, id.with("seed"), seed, seed_random);
+                                changed |= faces_widget(ui, num_faces);
+                                changed |= mesh_widget(ui, id, mesh);
+                                enable_generate = image.is_some();
+                                if let Some(stage) = model_stage_buttons(ui, enable_generate, mesh) {
+                                    cmd.trigger_targets(Generate(Some(stage)), e);
+                                }
+                            }
+                            Workflow::MultiViewToModel {
+                                image,
+                                model,
+                                seed,
+                                seed_random,
+                                num_faces,
+                                views,
+                                mesh,
+                            } => {
+                                image_widget(ui, image);
+                                model_widget(ui, model);
+                                for view in ModelViews::NAMES {
+                                    let Some(linked) = views.get_mut(view) else {
+                                        continue;
+                                    };
+                                    ui.label(format!(
+                                        "{}{} View",
+                                        &view[..1].to_uppercase(),
+                                        &view[1..]
+                                    ));
+                                    let none = if view == "front" {
+                                        "This prefab"
+                                    } else {
+                                        "None"
+                                    };
+                                    changed |=
+                                        board_image_combo(ui, id.with(view), linked, &board, none);
+                                    ui.end_row();
+                                }
+                                changed |= seed_wigit(ui, id.with("seed"), seed, seed_random);
+                                changed |= faces_widget(ui, num_faces);
+                                changed |= mesh_widget(ui, id, mesh);
+                                enable_generate = views.front.is_some() || image.is_some();
+                                if let Some(stage) = model_stage_buttons(ui, enable_generate, mesh) {
+                                    cmd.trigger_targets(Generate(Some(stage)), e);
+                                }
+                            }
+                            Workflow::Custom {
+                                template,
+                                params,
+                                outputs,
+                            } => {
+                                match templates.get(template, &template_assets) {
+                                    Ok(template) => {
+                                        changed |= params_widget(
+                                            ui,
+                                            id,
+                                            template,
+                                            params,
+                                            &board,
+                                            &comfy_models,
+                                        );
+                                    }
+                                    Err(err) => {
+                                        ui.label("Template");
+                                        ui.colored_label(
+                                            ui.visuals().error_fg_color,
+                                            err.to_string(),
+                                        );
+                                        ui.end_row();
+                                        enable_generate = false;
+                                    }
+                                }
+                                image_widget(ui, &mut outputs.image);
+                                model_widget(ui, &mut outputs.model);
                             }
                         }
 
+                        let problems = diagnostics
+                            .for_templates(&p.workflow.templates())
+                            .count();
+                        if problems > 0 {
+                            ui.label("Diagnostics");
+                            ui.colored_label(
+                                ui.visuals().warn_fg_color,
+                                format!("{} problems, see Diagnostics", problems),
+                            );
+                            ui.end_row();
+                        }
+
                         ui.label("");
                         if ui
                             .add_enabled(
@@ -390,7 +490,181 @@ pub fn ui_select(world: &mut World) {
                             cmd.trigger_targets(Generate(None), e);
                         }
                         ui.end_row();
+
+                        ui.label("");
+                        if ui
+                            .add_enabled(
+                                p.workflow.image().is_some(),
+                                egui::Button::new("Variation")
+                                    .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+                            )
+                            .on_hover_text("Copy this prefab and refine the copy from its image")
+                            .clicked()
+                        {
+                            cmd.trigger_targets(Variation, e);
+                        }
+                        ui.end_row();
+
+                        ui.label("Metadata");
+                        ui.horizontal(|ui| {
+                            for (label, file) in
+                                [("Image", p.workflow.image()), ("Model", p.workflow.model())]
+                            {
+                                let clicked = ui
+                                    .add_enabled(file.is_some(), egui::Button::new(label))
+                                    .clicked();
+                                if let Some(file) = file.filter(|_| clicked) {
+                                    inspector.open(file);
+                                }
+                            }
+                        });
+                        ui.end_row();
+
+                        if !p.variants.is_empty() {
+                            ui.label("Variants");
+                            ui.vertical(|ui| {
+                                ui.label(format!(
+                                    "{} more from the last batch, click one under the image to swap it in",
+                                    p.variants.len()
+                                ));
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .button("Keep")
+                                        .on_hover_text("Make each variant its own prefab")
+                                        .clicked()
+                                    {
+                                        cmd.trigger_targets(KeepVariants, e);
+                                    }
+                                    if ui
+                                        .button("Discard")
+                                        .on_hover_text("Delete the variants, keeping the image")
+                                        .clicked()
+                                    {
+                                        cmd.trigger_targets(DiscardVariants, e);
+                                    }
+                                });
+                            });
+                            ui.end_row();
+                        }
+
+                        ui.label("Inpaint");
+                        ui.vertical(|ui| {
+                            let mut painting = mask.is_some();
+                            if ui
+                                .add_enabled(
+                                    p.workflow.image().is_some(),
+                                    egui::Checkbox::new(&mut painting, "Paint mask"),
+                                )
+                                .on_hover_text("Paint on the image with the left mouse button")
+                                .changed()
+                            {
+                                cmd.trigger_targets(ToggleMask, e);
+                            }
+                            let Some(mask) = mask.as_mut() else {
+                                return;
+                            };
+                            ui.horizontal(|ui| {
+                                ui.label("Brush");
+                                ui.add(egui::DragValue::new(&mut mask.radius).range(1.0..=512.0));
+                                ui.toggle_value(&mut mask.erase, "Erase");
+                                if ui.button("Clear").clicked() {
+                                    cmd.trigger_targets(ClearMask, e);
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Grow");
+                                ui.add(egui::DragValue::new(&mut mask.grow).range(0..=64));
+                            });
+                            ui.add(
+                                egui::TextEdit::multiline(&mut mask.prompt)
+                                    .hint_text("What to paint in the mask"),
+                            );
+                            if ui
+                                .add(
+                                    egui::Button::new("Inpaint")
+                                        .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+                                )
+                                .on_hover_text(
+                                    "Regenerate the masked area, the previous image stays in the history",
+                                )
+                                .clicked()
+                            {
+                                cmd.trigger_targets(Inpaint, e);
+                            }
+                        });
+                        ui.end_row();
+
+                        if p.workflow.model().is_some() {
+                            ui.label("Retexture");
+                            ui.vertical(|ui| {
+                                // picked reference only lives in the ui until used
+                                let reference_id = id.with("retexture reference");
+                                let mut reference = ui
+                                    .data(|d| d.get_temp::<Option<String>>(reference_id))
+                                    .flatten();
+                                board_image_combo(
+                                    ui,
+                                    reference_id,
+                                    &mut reference,
+                                    &board,
+                                    "This prefab",
+                                );
+                                ui.data_mut(|d| d.insert_temp(reference_id, reference.clone()));
+                                if ui
+                                    .add_enabled(
+                                        reference.is_some() || p.workflow.image().is_some(),
+                                        egui::Button::new("Retexture")
+                                            .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+                                    )
+                                    .on_hover_text(
+                                        "Paint the current model again from the reference, the previous model stays in the history",
+                                    )
+                                    .clicked()
+                                {
+                                    cmd.trigger_targets(Retexture(reference), e);
+                                }
+                            });
+                            ui.end_row();
+                        }
+
+                        if !p.history.is_empty() {
+                            ui.label("History");
+                            if let Some(index) =
+                                history_widget(ui, id, &p.history, &mut inspector)
+                            {
+                                cmd.trigger_targets(Revert(index), e);
+                            }
+                            ui.end_row();
+                        }
+
+                        if let Some(progress) = progress {
+                            progress_widget(ui, progress);
+
+                            ui.label("");
+                            if ui
+                                .add(
+                                    egui::Button::new("Cancel")
+                                        .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+                                )
+                                .clicked()
+                            {
+                                cmd.trigger_targets(Cancel, e);
+                            }
+                            ui.end_row();
+                        }
+
+                        if let Some(failed) = failed {
+                            ui.label("Error");
+                            ui.vertical(|ui| {
+                                ui.colored_label(ui.visuals().error_fg_color, &failed.error);
+                                if ui.button("Retry").clicked() {
+                                    cmd.trigger_targets(Retry, e);
+                                }
+                            });
+                            ui.end_row();
+                        }
                     });
+                compare_window(ui.ctx(), id, &p.history, &mut textures, &asset_server);
             }
             //ui_for_entities_filtered(world, ui, &Filter::<(With<Prefab>, With<Selected>)>::all());
 
@@ -403,6 +677,523 @@ pub fn ui_select(world: &mut World) {
     system_state.apply(world);
 }
 
+/// what's embedded in a generated png or glb, opened from the Select window
+pub fn ui_metadata(
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    mut inspector: ResMut<MetadataInspector>,
+) {
+    if !inspector.open {
+        return;
+    }
+    let inspector = inspector.as_mut();
+    let mut reload = false;
+
+    egui::Window::new("Metadata")
+        .open(&mut inspector.open)
+        .show(egui_context.get_mut(), |ui| {
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut inspector.path)
+                        .hint_text("png or glb, relative to assets"),
+                );
+                reload |= response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                reload |= ui.button("Read").clicked();
+            });
+            ui.separator();
+
+            let metadata = match &inspector.metadata {
+                Some(Ok(metadata)) => metadata,
+                Some(Err(err)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                    return;
+                }
+                None => return,
+            };
+            egui::Grid::new("metadata")
+                .num_columns(2)
+                .spacing([16.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    let none = "None".to_string();
+                    ui.label("Prompt");
+                    ui.label(metadata.prompt.as_ref().unwrap_or(&none));
+                    ui.end_row();
+
+                    ui.label("Negative");
+                    ui.label(metadata.negative.as_ref().unwrap_or(&none));
+                    ui.end_row();
+
+                    ui.label("Seed");
+                    match metadata.seed {
+                        Some(seed) => {
+                            if ui.button(seed.to_string()).on_hover_text("Copy").clicked() {
+                                ui.ctx().copy_text(seed.to_string());
+                            }
+                        }
+                        None => {
+                            ui.label(&none);
+                        }
+                    }
+                    ui.end_row();
+
+                    ui.label("Checkpoint");
+                    ui.label(metadata.checkpoint.as_ref().unwrap_or(&none));
+                    ui.end_row();
+
+                    ui.label("LoRAs");
+                    if metadata.loras.is_empty() {
+                        ui.label(&none);
+                    } else {
+                        ui.label(metadata.loras.join("\n"));
+                    }
+                    ui.end_row();
+
+                    ui.label("Workflow");
+                    match &metadata.workflow {
+                        Some(workflow) => {
+                            if ui
+                                .button("Copy")
+                                .on_hover_text("Copy the workflow as ComfyUI api json")
+                                .clicked()
+                            {
+                                ui.ctx().copy_text(
+                                    serde_json::to_string_pretty(workflow).unwrap_or_default(),
+                                );
+                            }
+                        }
+                        None => {
+                            ui.label(&none);
+                        }
+                    }
+                    ui.end_row();
+                });
+        });
+
+    if reload {
+        inspector.reload();
+    }
+}
+
+/// picks images and models to import, for when drag and drop doesn't arrive
+pub fn ui_import(
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    mut dialog: ResMut<ImportDialog>,
+    mut commands: Commands,
+) {
+    if !dialog.open {
+        return;
+    }
+    let dialog = dialog.as_mut();
+    let mut open = dialog.open;
+    let mut go_to = None;
+    let mut import = false;
+
+    egui::Window::new("Import")
+        .open(&mut open)
+        .show(egui_context.get_mut(), |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Up").clicked() {
+                    go_to = dialog.dir.parent().map(|p| p.to_path_buf());
+                }
+                ui.label(dialog.dir.to_string_lossy());
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    let file_name = |p: &PathBuf| {
+                        p.file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string()
+                    };
+                    for folder in dialog.folders.iter() {
+                        if ui
+                            .selectable_label(false, format!("{}/", file_name(folder)))
+                            .clicked()
+                        {
+                            go_to = Some(folder.clone());
+                        }
+                    }
+                    for file in dialog.files.iter() {
+                        let mut checked = dialog.selected.contains(file);
+                        if ui.checkbox(&mut checked, file_name(file)).changed() {
+                            if checked {
+                                dialog.selected.insert(file.clone());
+                            } else {
+                                dialog.selected.remove(file);
+                            }
+                        }
+                    }
+                    if dialog.folders.is_empty() && dialog.files.is_empty() {
+                        ui.label("No images or models here");
+                    }
+                });
+            ui.separator();
+
+            import = ui
+                .add_enabled(
+                    !dialog.selected.is_empty(),
+                    egui::Button::new(format!("Import {}", dialog.selected.len())),
+                )
+                .on_hover_text("png, jpg, webp, bmp, glb, gltf or obj")
+                .clicked();
+        });
+
+    if import {
+        commands.send_event(ImportFiles(dialog.selected.iter().cloned().collect()));
+        dialog.selected.clear();
+        open = false;
+    }
+    if let Some(dir) = go_to {
+        dialog.set_dir(dir);
+    }
+    dialog.open = open;
+}
+
+/// connection settings, edits a copy so the resource only changes when something did
+pub fn ui_comfy(
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    mut comfy: ResMut<ComfyClient>,
+) {
+    let mut settings = comfy.clone();
+    let mut changed = false;
+
+    egui::Window::new("ComfyUI")
+        .default_open(false)
+        .show(egui_context.get_mut(), |ui| {
+            egui::Grid::new("comfy settings")
+                .num_columns(2)
+                .spacing([16.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Status");
+                    if settings.connected() {
+                        ui.label("Connected");
+                    } else {
+                        ui.colored_label(ui.visuals().warn_fg_color, "Disconnected");
+                    }
+                    ui.end_row();
+
+                    ui.label("Host");
+                    changed |= ui.text_edit_singleline(&mut settings.host).changed();
+                    ui.end_row();
+
+                    ui.label("Scheme");
+                    egui::ComboBox::from_id_salt("comfy scheme")
+                        .selected_text(format!("{}", settings.scheme))
+                        .show_ui(ui, |ui| {
+                            for variant in ComfyScheme::iter() {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut settings.scheme,
+                                        variant,
+                                        format!("{}", variant),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Connect Timeout");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut settings.connect_timeout)
+                                .range(0.5..=120.0)
+                                .suffix(" s"),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Request Timeout");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut settings.request_timeout)
+                                .range(1.0..=600.0)
+                                .suffix(" s"),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Input Folder")
+                        .on_hover_text("ComfyUI's input folder, as seen from the server");
+                    changed |= ui.text_edit_singleline(&mut settings.input_dir).changed();
+                    ui.end_row();
+                });
+        });
+
+    if changed {
+        settings.rebuild();
+        *comfy = settings;
+    }
+}
+
+/// problems found checking the workflow templates against the connected ComfyUI
+pub fn ui_diagnostics(
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    info: Res<ObjectInfo>,
+    diagnostics: Res<WorkflowDiagnostics>,
+    comfy: Res<ComfyClient>,
+    runtime: Res<TokioTasksRuntime>,
+) {
+    let count = diagnostics.count();
+    egui::Window::new(format!("Diagnostics ({})", count))
+        .id(egui::Id::new("diagnostics"))
+        .default_open(false)
+        .show(egui_context.get_mut(), |ui| {
+            if info.0.is_none() {
+                ui.label("Not connected to ComfyUI, nothing checked yet");
+            }
+            // nodes or models can be installed without restarting ComfyUI
+            if ui
+                .add_enabled(comfy.connected(), egui::Button::new("Recheck"))
+                .clicked()
+            {
+                let comfy = comfy.clone();
+                runtime.spawn_background_task(async move |mut ctx| {
+                    fetch_object_info(&comfy, &mut ctx).await;
+                    fetch_models(&comfy, &mut ctx).await;
+                });
+            }
+            ui.separator();
+
+            for (name, found) in diagnostics.0.iter() {
+                if found.is_empty() {
+                    ui.label(format!("{} ✔", name));
+                    continue;
+                }
+                egui::CollapsingHeader::new(format!("{} ({})", name, found.len()))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        for diagnostic in found {
+                            ui.colored_label(ui.visuals().warn_fg_color, diagnostic.to_string());
+                        }
+                    });
+            }
+        });
+}
+
+/// running and pending generations, pending ones can be reordered or removed
+pub fn ui_queue(
+    mut egui_context: Single<&mut EguiContext, With<PrimaryWindow>>,
+    mut queue: ResMut<GenerationQueue>,
+    mut commands: Commands,
+) {
+    let mut max_in_flight = queue.max_in_flight;
+    let mut action = None;
+
+    egui::Window::new("Queue")
+        .default_open(false)
+        .show(egui_context.get_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Max In Flight");
+                ui.add(egui::DragValue::new(&mut max_in_flight).range(1..=16));
+            });
+            ui.separator();
+
+            if queue.is_empty() {
+                ui.label("Nothing queued");
+            }
+
+            egui::Grid::new("generation queue")
+                .num_columns(4)
+                .spacing([8.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    for job in queue.running.iter() {
+                        ui.label(&job.name);
+                        ui.label(job.stage_label());
+                        ui.label("Running");
+                        if ui.small_button("✖").on_hover_text("Cancel").clicked() {
+                            commands.trigger_targets(Cancel, job.entity);
+                        }
+                        ui.end_row();
+                    }
+
+                    let count = queue.pending.len();
+                    for (i, job) in queue.pending.iter().enumerate() {
+                        ui.label(&job.name);
+                        ui.label(job.stage_label());
+                        let mut priority = job.priority;
+                        if ui
+                            .add(egui::DragValue::new(&mut priority).prefix("priority "))
+                            .changed()
+                        {
+                            action = Some(QueueAction::Priority(job.entity, priority));
+                        }
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(i > 0, egui::Button::new("⏶").small()).clicked() {
+                                action = Some(QueueAction::Up(i));
+                            }
+                            if ui
+                                .add_enabled(i + 1 < count, egui::Button::new("⏷").small())
+                                .clicked()
+                            {
+                                action = Some(QueueAction::Down(i));
+                            }
+                            if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                                commands.trigger_targets(Cancel, job.entity);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+
+    // only touch the resource when something changed
+    if max_in_flight != queue.max_in_flight {
+        queue.max_in_flight = max_in_flight;
+    }
+    match action {
+        Some(QueueAction::Up(i)) => queue.move_up(i),
+        Some(QueueAction::Down(i)) => queue.move_down(i),
+        Some(QueueAction::Priority(e, priority)) => queue.set_priority(e, priority),
+        None => {}
+    }
+}
+
+enum QueueAction {
+    Up(usize),
+    Down(usize),
+    Priority(Entity, i32),
+}
+
+fn progress_widget(ui: &mut egui::Ui, progress: &WorkflowProgress) {
+    ui.label("Progress");
+    let node = match (&progress.title, &progress.node) {
+        (Some(title), Some(node)) => format!("{} ({})", title, node),
+        (None, Some(node)) => node.clone(),
+        _ => "Queued".to_string(),
+    };
+    let steps = if progress.max > 0 {
+        format!(" {}/{}", progress.value, progress.max)
+    } else {
+        String::new()
+    };
+    ui.add(
+        egui::ProgressBar::new(progress.value())
+            .text(format!(
+                "{}/{} {}{}",
+                progress.stage + 1,
+                progress.stages,
+                node,
+                steps
+            ))
+            .desired_width(ui.available_width()),
+    );
+    ui.end_row();
+}
+
+/// Hunyuan3D shape and texture settings
+fn mesh_widget(ui: &mut egui::Ui, id: egui::Id, mesh: &mut MeshSettings) -> bool {
+    let mut changed = false;
+
+    ui.label("Shape");
+    ui.horizontal(|ui| {
+        ui.label("Octree");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut mesh.octree_resolution)
+                    .range(64..=1024)
+                    .speed(8),
+            )
+            .changed();
+        ui.label("Steps");
+        changed |= ui
+            .add(egui::DragValue::new(&mut mesh.steps).range(1..=200))
+            .changed();
+        ui.label("Guidance");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut mesh.guidance)
+                    .range(0.0..=30.0)
+                    .speed(0.1),
+            )
+            .changed();
+    });
+    ui.end_row();
+
+    ui.label("Background");
+    changed |= ui
+        .checkbox(&mut mesh.remove_background, "Remove")
+        .on_hover_text("Turn off for images already on a plain background")
+        .changed();
+    ui.end_row();
+
+    ui.label("Texture");
+    ui.horizontal(|ui| {
+        changed |= ui.checkbox(&mut mesh.texture, "Texture").changed();
+        ui.add_enabled_ui(mesh.texture, |ui| {
+            changed |= ui
+                .checkbox(&mut mesh.delight, "Delight")
+                .on_hover_text("Remove lighting baked into the image before texturing")
+                .changed();
+            egui::ComboBox::from_id_salt(id.with("texture size"))
+                .selected_text(format!("{}", mesh.texture_size))
+                .show_ui(ui, |ui| {
+                    for size in TEXTURE_SIZES {
+                        changed |= ui
+                            .selectable_value(&mut mesh.texture_size, size, format!("{}", size))
+                            .changed();
+                    }
+                });
+        });
+    });
+    ui.end_row();
+
+    changed
+}
+
+/// shape only and texture buttons of the model workflows, returns the stage clicked
+fn model_stage_buttons(ui: &mut egui::Ui, enabled: bool, mesh: &MeshSettings) -> Option<u8> {
+    let mut stage = None;
+
+    ui.label("");
+    if ui
+        .add_enabled(
+            enabled,
+            egui::Button::new("Generate Shape")
+                .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+        )
+        .on_hover_text("Untextured, to check the geometry first")
+        .clicked()
+    {
+        stage = Some(SHAPE_STAGE);
+    }
+    ui.end_row();
+
+    ui.label("");
+    if ui
+        .add_enabled(
+            enabled && mesh.texture,
+            egui::Button::new("Generate Texture")
+                .min_size(egui::Vec2::new(ui.available_width(), 30.0)),
+        )
+        .on_hover_text("Textures the shape generated last")
+        .clicked()
+    {
+        stage = Some(TEXTURE_STAGE);
+    }
+    ui.end_row();
+
+    stage
+}
+
+fn faces_widget(ui: &mut egui::Ui, num_faces: &mut u32) -> bool {
+    ui.label("Faces");
+    let changed = ui
+        .add(
+            egui::Slider::new(num_faces, 0..=u32::MAX)
+                .text("Faces")
+                .step_by(1000.)
+                .clamping(egui::SliderClamping::Always),
+        )
+        .changed();
+    ui.end_row();
+    changed
+}
+
 fn image_widget(ui: &mut egui::Ui, p: &mut Option<String>) {
     ui.label("Image");
     if let Some(text) = p {
@@ -437,23 +1228,524 @@ fn prompt_widget(ui: &mut egui::Ui, prompt: &mut String) -> bool {
     changed
 }
 
-fn seed_wigit(ui: &mut egui::Ui, seed: &mut u32, random: &mut bool) -> bool {
+/// negative prompt, size and KSampler settings, sampler and scheduler lists come from ComfyUI
+fn sampler_widget(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    sampler: &mut SamplerSettings,
+    info: &ObjectInfo,
+    template: Option<&WorkflowTemplate>,
+) -> bool {
     let mut changed = false;
 
-    ui.label("Seed");
+    ui.label("Negative");
+    // shows the workflow's until edited
+    let mut negative = sampler
+        .negative
+        .clone()
+        .or_else(|| match template?.default_value("negative") {
+            Some(ParamValue::String(s)) => Some(s),
+            _ => None,
+        })
+        .unwrap_or_default();
+    if ui.text_edit_multiline(&mut negative).changed() {
+        sampler.negative = Some(negative);
+        changed = true;
+    }
+    ui.end_row();
+
+    ui.label("Size");
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_salt(id.with("size"))
+            .selected_text(sampler.preset().unwrap_or("Custom"))
+            .width(60.0)
+            .show_ui(ui, |ui| {
+                for (label, width, height) in SIZE_PRESETS {
+                    let text = format!("{} {}x{}", label, width, height);
+                    if ui
+                        .selectable_label(sampler.preset() == Some(label), text)
+                        .clicked()
+                    {
+                        sampler.width = width;
+                        sampler.height = height;
+                        changed = true;
+                    }
+                }
+            });
+        changed |= ui
+            .add(egui::DragValue::new(&mut sampler.width).range(64..=4096).speed(8))
+            .changed();
+        ui.label("x");
+        changed |= ui
+            .add(egui::DragValue::new(&mut sampler.height).range(64..=4096).speed(8))
+            .changed();
+    });
+    ui.end_row();
+
+    ui.label("Batch");
     changed |= ui
-        .add(
-            egui::Slider::new(seed, 0..=u32::MAX)
-                .text("Seed")
-                .clamping(egui::SliderClamping::Always),
-        )
+        .add(egui::DragValue::new(&mut sampler.batch_size).range(1..=16))
+        .on_hover_text("Images per run, the extras are shown under the image to pick from")
         .changed();
     ui.end_row();
 
-    ui.label("Seed");
-    if ui.checkbox(random, "Randomize").changed() {
-        changed = true;
+    ui.label("Steps");
+    changed |= ui
+        .add(egui::DragValue::new(&mut sampler.steps).range(1..=200))
+        .changed();
+    ui.end_row();
+
+    ui.label("CFG");
+    changed |= ui
+        .add(egui::DragValue::new(&mut sampler.cfg).range(0.0..=30.0).speed(0.1))
+        .changed();
+    ui.end_row();
+
+    ui.label("Sampler");
+    changed |= name_combo(
+        ui,
+        id.with("sampler"),
+        &mut sampler.sampler,
+        &info.options("KSampler", "sampler_name"),
+    );
+    ui.end_row();
+
+    ui.label("Scheduler");
+    changed |= name_combo(
+        ui,
+        id.with("scheduler"),
+        &mut sampler.scheduler,
+        &info.options("KSampler", "scheduler"),
+    );
+    ui.end_row();
+
+    changed
+}
+
+fn img2img_widget(ui: &mut egui::Ui, img2img: &mut Img2ImgSettings, has_image: bool) -> bool {
+    let mut changed = false;
+    ui.label("Start From");
+    ui.horizontal(|ui| {
+        changed |= ui
+            .add_enabled(
+                has_image,
+                egui::Checkbox::new(&mut img2img.enabled, "Current image"),
+            )
+            .changed();
+        if img2img.enabled {
+            changed |= ui
+                .add(egui::Slider::new(&mut img2img.denoise, 0.0..=1.0).text("Denoise"))
+                .changed();
+        }
+    });
+    ui.end_row();
+    changed
+}
+
+/// checkpoint, vae and lora stack, "Workflow default" keeps what the template has
+fn models_widget(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    models: &mut ModelSettings,
+    lists: &ComfyModels,
+    template: Option<&WorkflowTemplate>,
+) -> bool {
+    let mut changed = false;
+
+    ui.label("Checkpoint");
+    changed |= model_combo(
+        ui,
+        id.with("checkpoint"),
+        &mut models.checkpoint,
+        lists.get(CHECKPOINTS),
+    );
+    ui.end_row();
+
+    ui.label("VAE");
+    changed |= model_combo(ui, id.with("vae"), &mut models.vae, lists.get(VAES));
+    ui.end_row();
+
+    ui.label("LoRAs");
+    ui.vertical(|ui| {
+        let Some(loras) = &mut models.loras else {
+            ui.horizontal(|ui| {
+                ui.label("Workflow default");
+                if ui.button("Customize").clicked() {
+                    models.loras = Some(
+                        template
+                            .map(ModelSettings::template_loras)
+                            .unwrap_or_default(),
+                    );
+                    changed = true;
+                }
+            });
+            return;
+        };
+        let mut remove = None;
+        for (i, lora) in loras.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                changed |=
+                    name_combo(ui, id.with(("lora", i)), &mut lora.name, lists.get(LORAS));
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut lora.strength_model)
+                            .range(-10.0..=10.0)
+                            .speed(0.01)
+                            .prefix("model "),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut lora.strength_clip)
+                            .range(-10.0..=10.0)
+                            .speed(0.01)
+                            .prefix("clip "),
+                    )
+                    .changed();
+                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            loras.remove(i);
+            changed = true;
+        }
+        let mut reset = false;
+        ui.horizontal(|ui| {
+            if ui.button("Add").clicked() {
+                loras.push(LoraSettings::default());
+                changed = true;
+            }
+            reset = ui.button("Workflow default").clicked();
+        });
+        if reset {
+            models.loras = None;
+            changed = true;
+        }
+    });
+    ui.end_row();
+
+    changed
+}
+
+/// None shows as "Workflow default"
+fn model_combo(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    value: &mut Option<String>,
+    options: &[String],
+) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(value.as_deref().unwrap_or("Workflow default"))
+        .show_ui(ui, |ui| {
+            changed |= ui.selectable_value(value, None, "Workflow default").changed();
+            for option in options {
+                changed |= ui
+                    .selectable_value(value, Some(option.clone()), option)
+                    .changed();
+            }
+        });
+    changed
+}
+
+/// falls back to a text field until the list is fetched from ComfyUI
+fn name_combo(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    value: &mut String,
+    options: &[String],
+) -> bool {
+    if options.is_empty() {
+        return ui.text_edit_singleline(value).changed();
+    }
+    let mut changed = false;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(value.as_str())
+        .show_ui(ui, |ui| {
+            for option in options {
+                changed |= ui.selectable_value(value, option.clone(), option).changed();
+            }
+        });
+    changed
+}
+
+/// one row per param the template manifest shows, values that dont match
+/// their declared kind (template changed) are reset to the workflow default
+fn params_widget(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    template: &WorkflowTemplate,
+    params: &mut BTreeMap<String, ParamValue>,
+    board: &[(String, String)],
+    models: &ComfyModels,
+) -> bool {
+    let mut changed = false;
+    for (name, kind) in template.visible_params() {
+        let Some(default) = template.default_value(name) else {
+            continue;
+        };
+        let value = params.entry(name.clone()).or_insert_with(|| default.clone());
+        if std::mem::discriminant(value) != std::mem::discriminant(&default) {
+            *value = default;
+            changed = true;
+        }
+
+        if let ParamValue::Seed { seed, random } = value {
+            changed |= seed_wigit(ui, id.with(name), seed, random);
+            continue;
+        }
+
+        ui.label(name);
+        match (kind, value) {
+            (ParamKind::String { multiline: true }, ParamValue::String(s)) => {
+                changed |= ui.text_edit_multiline(s).changed();
+            }
+            (ParamKind::Model(folder), ParamValue::String(s)) => {
+                changed |= name_combo(ui, name, s, models.get(folder));
+            }
+            (ParamKind::Enum(options), ParamValue::String(s)) => {
+                egui::ComboBox::from_id_salt(name)
+                    .selected_text(s.as_str())
+                    .show_ui(ui, |ui| {
+                        for option in options {
+                            changed |= ui
+                                .selectable_value(s, option.clone(), option)
+                                .changed();
+                        }
+                    });
+            }
+            (_, ParamValue::String(s)) => {
+                changed |= ui.text_edit_singleline(s).changed();
+            }
+            (ParamKind::Int { min, max }, ParamValue::Int(i)) => {
+                changed |= ui
+                    .add(egui::DragValue::new(i).range(*min..=*max))
+                    .changed();
+            }
+            (ParamKind::Float { min, max }, ParamValue::Float(f)) => {
+                changed |= ui
+                    .add(egui::DragValue::new(f).range(*min..=*max).speed(0.01))
+                    .changed();
+            }
+            (_, ParamValue::Bool(b)) => {
+                changed |= ui.checkbox(b, "").changed();
+            }
+            (_, ParamValue::Image(image)) => {
+                changed |= board_image_combo(ui, name, image, board, "None");
+            }
+            _ => {
+                ui.label("");
+            }
+        }
+        ui.end_row();
+    }
+    changed
+}
+
+/// newest first, returns the version to revert to,
+/// ticking two versions compares them in [`compare_window`]
+fn history_widget(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    history: &[Version],
+    inspector: &mut MetadataInspector,
+) -> Option<usize> {
+    let mut revert = None;
+    let compare_id = id.with("compare");
+    let mut compare = ui
+        .data(|d| d.get_temp::<Vec<usize>>(compare_id))
+        .unwrap_or_default();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    egui::CollapsingHeader::new(format!("{} versions", history.len()))
+        .id_salt(id.with("history"))
+        .show(ui, |ui| {
+            for (i, version) in history.iter().enumerate().rev() {
+                ui.horizontal(|ui| {
+                    let mut ticked = compare.contains(&i);
+                    if ui
+                        .checkbox(&mut ticked, "")
+                        .on_hover_text("Compare")
+                        .changed()
+                    {
+                        compare.retain(|c| *c != i);
+                        if ticked {
+                            compare.push(i);
+                            // only ever compare two, drop the oldest pick
+                            if compare.len() > 2 {
+                                compare.remove(0);
+                            }
+                        }
+                    }
+                    ui.label(&version.label)
+                        .on_hover_text(version_details(version));
+                    ui.weak(ago(now.saturating_sub(version.timestamp)));
+                    if ui.small_button("Revert").clicked() {
+                        revert = Some(i);
+                    }
+                    if ui.small_button("Metadata").clicked() {
+                        inspector.open(&version.file);
+                    }
+                });
+            }
+        });
+    ui.data_mut(|d| d.insert_temp(compare_id, compare));
+    revert
+}
+
+/// shows the two versions ticked in [`history_widget`] side by side
+fn compare_window(
+    ctx: &egui::Context,
+    id: egui::Id,
+    history: &[Version],
+    textures: &mut EguiUserTextures,
+    asset_server: &AssetServer,
+) {
+    let compare_id = id.with("compare");
+    let compare = ctx
+        .data(|d| d.get_temp::<Vec<usize>>(compare_id))
+        .unwrap_or_default();
+    let versions = compare
+        .iter()
+        .filter_map(|i| history.get(*i))
+        .collect::<Vec<_>>();
+    if versions.len() != 2 {
+        return;
+    }
+
+    let mut open = true;
+    egui::Window::new("Compare")
+        .id(id.with("compare window"))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.columns(2, |columns| {
+                for (ui, version) in columns.iter_mut().zip(versions) {
+                    ui.strong(&version.label);
+                    match version.output {
+                        OutputKind::Image => {
+                            let handle = asset_server.load::<Image>(&version.file);
+                            let texture = textures
+                                .image_id(&handle)
+                                .unwrap_or_else(|| textures.add_image(handle));
+                            let width = ui.available_width();
+                            ui.add(egui::Image::new(egui::load::SizedTexture::new(
+                                texture,
+                                [width, width],
+                            )));
+                        }
+                        OutputKind::Model => {
+                            ui.weak("Revert to view the model");
+                        }
+                    }
+                    ui.label(version_details(version));
+                }
+            });
+        });
+    if !open {
+        ctx.data_mut(|d| d.remove::<Vec<usize>>(compare_id));
+    }
+}
+
+fn version_details(version: &Version) -> String {
+    let mut details = format!("{:.1}s", version.duration);
+    if let Some(seed) = version.seed {
+        details += &format!("\nSeed {}", seed);
+    }
+    if let Some(prompt) = &version.prompt {
+        details += &format!("\n{}", prompt);
+    }
+    if let Some(workflow) = &version.workflow {
+        details += &format!("\n{}", workflow);
     }
+    details
+}
+
+/// rough age from seconds
+fn ago(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+/// picks the image of a prefab on the board, `none` labels leaving it unset
+fn board_image_combo(
+    ui: &mut egui::Ui,
+    id_salt: impl std::hash::Hash,
+    image: &mut Option<String>,
+    board: &[(String, String)],
+    none: &str,
+) -> bool {
+    let mut changed = false;
+    let selected = board
+        .iter()
+        .find(|(_, path)| Some(path) == image.as_ref())
+        .map(|(name, _)| name.as_str())
+        .unwrap_or(none);
+    egui::ComboBox::from_id_salt(id_salt)
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            changed |= ui.selectable_value(image, None, none).changed();
+            for (prefab, path) in board {
+                changed |= ui
+                    .selectable_value(image, Some(path.clone()), prefab)
+                    .changed();
+            }
+        });
+    changed
+}
+
+/// full 64 bit seed as text so ComfyUI seeds can be pasted, locked keeps it between generations
+fn seed_wigit(ui: &mut egui::Ui, id: egui::Id, seed: &mut u64, random: &mut bool) -> bool {
+    let mut changed = false;
+
+    ui.label("Seed");
+    ui.horizontal(|ui| {
+        // keep what is being typed, even if it isnt a number yet
+        let buffer_id = id.with("buffer");
+        let mut text = ui
+            .data(|d| d.get_temp::<String>(buffer_id))
+            .unwrap_or_else(|| seed.to_string());
+        let valid = text.trim().parse::<u64>().is_ok();
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut text)
+                .id(id)
+                .desired_width(180.0)
+                .text_color_opt((!valid).then(|| ui.visuals().error_fg_color)),
+        );
+        if response.changed() {
+            if let Ok(value) = text.trim().parse::<u64>() {
+                *seed = value;
+                changed = true;
+            }
+        }
+        if response.has_focus() {
+            ui.data_mut(|d| d.insert_temp(buffer_id, text));
+        } else {
+            ui.data_mut(|d| d.remove::<String>(buffer_id));
+        }
+
+        if ui.small_button("📋").on_hover_text("Copy").clicked() {
+            ui.ctx().copy_text(seed.to_string());
+        }
+
+        let mut locked = !*random;
+        if ui
+            .toggle_value(&mut locked, if locked { "🔒" } else { "🔓" })
+            .on_hover_text("Locked seeds are kept, unlocked get a new one each generation")
+            .changed()
+        {
+            *random = !locked;
+            changed = true;
+        }
+    });
     ui.end_row();
 
     changed
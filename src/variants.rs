@@ -0,0 +1,267 @@
+use std::path::Path;
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+
+use crate::{create_unique_name, tmp_path, PlaneSize, Prefab, Workflow};
+
+/// where batch variants are written, relative to assets
+const VARIANTS_DIR: &str = "ref/variants";
+/// half size of a thumbnail in the strip under the image
+const THUMB_SIZE: f32 = 0.2;
+const THUMB_GAP: f32 = 0.05;
+
+/// path the `index`th extra image of a batch is written to, each prefab has its own
+/// folder so names ending in a number cant collide
+pub fn variant_path(name: &str, index: usize) -> String {
+    Path::new(VARIANTS_DIR)
+        .join(name)
+        .join(format!("{}.png", index))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// moves the variants to the prefab's new name, a variant that cant be moved keeps its path
+pub fn rename_variants(variants: &mut [String], name: &str) {
+    for (i, variant) in variants.iter_mut().enumerate() {
+        // the first image of a batch is the prefab's own
+        let new_variant = variant_path(name, i + 1);
+        let src = Path::new("assets").join(&*variant);
+        let dst = Path::new("assets").join(&new_variant);
+        let result = dst
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::rename(&src, &dst));
+        if let Err(err) = result {
+            error!(
+                "Failed to move variant {} to {}: {}",
+                variant, new_variant, err
+            );
+            continue;
+        }
+        remove_variant_dir(&src);
+        *variant = new_variant;
+    }
+}
+
+/// removes the folder a variant was in once it is empty
+fn remove_variant_dir(file: &Path) {
+    if let Some(dir) = file.parent() {
+        // fails while other variants are left
+        std::fs::remove_dir(dir).ok();
+    }
+}
+
+/// Thumbnail of a variant under a prefab's image, clicking it promotes the variant
+#[derive(Component, Debug)]
+pub struct VariantThumb(pub usize);
+
+/// Thumbnails spawned for a prefab, respawned when its variants change
+#[derive(Component, Debug, Default)]
+pub struct VariantStrip {
+    variants: Vec<String>,
+    plane: Vec2,
+    thumbs: Vec<Entity>,
+}
+
+/// the extra images of a batch, replaces the previous batch's
+#[derive(Event)]
+pub struct SetVariants(pub Vec<String>);
+
+pub fn on_set_variants(trigger: Trigger<SetVariants>, mut query: Query<&mut Prefab>) {
+    let Ok(mut prefab) = query.get_mut(trigger.target()) else {
+        return;
+    };
+    // same names get overwritten, only a bigger old batch leaves files behind
+    for old in prefab.variants.iter().filter(|v| !trigger.0.contains(v)) {
+        let file = Path::new("assets").join(old);
+        std::fs::remove_file(&file).unwrap_or_default();
+        remove_variant_dir(&file);
+    }
+    prefab.variants = trigger.0.clone();
+}
+
+/// swaps a variant with the prefab's image, so the old image stays in the strip
+#[derive(Event)]
+pub struct PromoteVariant(pub usize);
+
+pub fn on_promote_variant(
+    trigger: Trigger<PromoteVariant>,
+    query: Query<&Prefab>,
+    asset_server: Res<AssetServer>,
+) {
+    let Ok(prefab) = query.get(trigger.target()) else {
+        return;
+    };
+    let (Some(image), Some(variant)) = (prefab.workflow.image(), prefab.variants.get(trigger.0))
+    else {
+        return;
+    };
+    let image_file = Path::new("assets").join(image);
+    let variant_file = Path::new("assets").join(variant);
+    let tmp = tmp_path(&variant_file);
+    let result = std::fs::rename(&image_file, &tmp)
+        .and_then(|_| std::fs::rename(&variant_file, &image_file))
+        .and_then(|_| std::fs::rename(&tmp, &variant_file));
+    if let Err(err) = result {
+        error!("Failed to promote {:?}: {}", variant_file, err);
+        return;
+    }
+    asset_server.reload(image.clone());
+    asset_server.reload(variant.clone());
+}
+
+/// turns every variant into its own static image prefab next to this one
+#[derive(Event)]
+pub struct KeepVariants;
+
+pub fn on_keep_variants(
+    trigger: Trigger<KeepVariants>,
+    mut query: Query<(&mut Prefab, &Transform)>,
+    mut commands: Commands,
+) {
+    let e = trigger.target();
+    let mut names = query
+        .iter()
+        .map(|(p, _)| p.name.clone())
+        .collect::<Vec<_>>();
+    let Ok((mut prefab, transform)) = query.get_mut(e) else {
+        return;
+    };
+    let variants = std::mem::take(&mut prefab.variants);
+    for (i, variant) in variants.iter().enumerate() {
+        let name = create_unique_name(&prefab.name, names.clone());
+        let image = Path::new("ref")
+            .join(format!("{}.png", name))
+            .to_string_lossy()
+            .to_string();
+        let file = Path::new("assets").join(variant);
+        if let Err(err) = std::fs::rename(&file, Path::new("assets").join(&image)) {
+            error!("Failed to keep variant {}: {}", variant, err);
+            continue;
+        }
+        remove_variant_dir(&file);
+        names.push(name.clone());
+        let offset = Vec3::new(2.0 * (i + 1) as f32, 0.0, 0.1);
+        commands.spawn((
+            Transform::from_translation(transform.translation + transform.rotation * offset),
+            Name::new(name.clone()),
+            Prefab {
+                name,
+                workflow: Workflow::StaticImage { image: Some(image) },
+                ..default()
+            },
+        ));
+    }
+}
+
+/// deletes the variants, the prefab's image is kept
+#[derive(Event)]
+pub struct DiscardVariants;
+
+pub fn on_discard_variants(trigger: Trigger<DiscardVariants>, mut query: Query<&mut Prefab>) {
+    let Ok(mut prefab) = query.get_mut(trigger.target()) else {
+        return;
+    };
+    remove_variants(&std::mem::take(&mut prefab.variants));
+}
+
+/// deletes variant files and the folder they were in
+pub fn remove_variants(variants: &[String]) {
+    for variant in variants {
+        let file = Path::new("assets").join(variant);
+        std::fs::remove_file(&file).unwrap_or_default();
+        remove_variant_dir(&file);
+    }
+}
+
+fn on_thumb_click(
+    trigger: Trigger<Pointer<Click>>,
+    query: Query<(&VariantThumb, &ChildOf)>,
+    mut commands: Commands,
+) {
+    if trigger.button != PointerButton::Primary {
+        return;
+    }
+    if let Ok((thumb, child_of)) = query.get(trigger.target()) {
+        commands.trigger_targets(PromoteVariant(thumb.0), child_of.parent());
+    }
+}
+
+/// lays the variants out in a row under the prefab's image
+pub fn update_variant_strips(
+    mut query: Query<
+        (Entity, &Prefab, &PlaneSize, Option<&mut VariantStrip>),
+        Or<(Changed<Prefab>, Changed<PlaneSize>)>,
+    >,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+) {
+    for (e, prefab, plane, strip) in query.iter_mut() {
+        if let Some(strip) = &strip {
+            if strip.variants == prefab.variants && strip.plane == plane.0 {
+                continue;
+            }
+            for thumb in strip.thumbs.iter() {
+                commands.entity(*thumb).despawn();
+            }
+        }
+
+        let mesh = meshes.add(Plane3d::new(Vec3::Z, Vec2::splat(THUMB_SIZE)));
+        let step = 2.0 * THUMB_SIZE + THUMB_GAP;
+        let left = -(prefab.variants.len().saturating_sub(1) as f32) * step * 0.5;
+        let y = -plane.0.y - THUMB_GAP - THUMB_SIZE;
+        let thumbs = prefab
+            .variants
+            .iter()
+            .enumerate()
+            .map(|(i, variant)| {
+                commands
+                    .spawn((
+                        Name::new("Variant"),
+                        VariantThumb(i),
+                        Mesh3d(mesh.clone()),
+                        MeshMaterial3d(materials.add(StandardMaterial {
+                            base_color_texture: Some(asset_server.load(variant)),
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        })),
+                        Transform::from_xyz(left + step * i as f32, y, 0.0),
+                        NotShadowCaster,
+                        ChildOf { parent: e },
+                    ))
+                    .observe(on_thumb_click)
+                    .id()
+            })
+            .collect();
+
+        let new_strip = VariantStrip {
+            variants: prefab.variants.clone(),
+            plane: plane.0,
+            thumbs,
+        };
+        match strip {
+            Some(mut strip) => *strip = new_strip,
+            None => {
+                commands.entity(e).insert(new_strip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_paths_dont_collide() {
+        assert_ne!(variant_path("a_1", 1), variant_path("a", 11));
+        assert_eq!(
+            Path::new(&variant_path("car", 2)),
+            Path::new("ref/variants/car/2.png")
+        );
+    }
+}